@@ -0,0 +1,24 @@
+//! How long a short line takes with a deep stack of fractions beneath it.
+//!
+//! Run with `cargo bench`. Each line only touches the top of the stack, so its time should
+//! barely depend on how deep the stack is.
+use rcalc::Calculator;
+use std::time::Instant;
+
+/// The lines timed at each depth.
+const LINES: u32 = 200;
+
+fn main() {
+    for &depth in &[100, 1_000, 10_000] {
+        let mut calculator = Calculator::default();
+        // Fractions with large numerators and denominators make normalizing them expensive.
+        let entry = "2 200 ^ 3 200 ^ / ";
+        calculator.parse(&entry.repeat(depth)).unwrap();
+        let start = Instant::now();
+        for _ in 0..LINES {
+            calculator.parse("1 3 / +").unwrap();
+        }
+        let per_line = start.elapsed() / LINES;
+        println!("depth {:>6}: {:>10.1?} per line", depth, per_line);
+    }
+}
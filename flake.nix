@@ -16,7 +16,9 @@
         packages.rcalc = pkgs.callPackage ({}: {}) {};
         devShell = pkgs.mkShell {
           buildInputs = with pkgs; [
-            rust-bin.nightly.latest.default
+            (rust-bin.nightly.latest.default.override {
+              targets = [ "wasm32-unknown-unknown" ];
+            })
             cargo-watch
             cargo-bloat
           ];
@@ -0,0 +1,227 @@
+//! Rational approximations of irrational results.
+//!
+//! The number backends only provide exact arithmetic, so operators like `sqrt` and `root`
+//! compute an interval that contains the true result and then pick the simplest rational
+//! within it. The width of that interval is controlled by the precision, the
+//! number of decimal digits after the point that the result is accurate to.
+use crate::error::CalcError;
+use crate::number::{Int, Rational};
+use crate::{floor, int_pow, Interrupt};
+use alloc::string::ToString;
+use num_traits::Zero;
+
+/// The precision used until the user sets one with `prec`.
+pub const DEFAULT_PRECISION: usize = 32;
+
+/// Find the rational with the smallest denominator in the closed interval `[lo, hi]`.
+///
+/// This walks the continued fraction expansions of both ends until they differ.
+pub fn simplest_between(lo: Rational, hi: Rational) -> Rational {
+    if lo.sign() <= 0 && hi.sign() >= 0 {
+        Rational::zero()
+    } else if hi.sign() < 0 {
+        -simplest_between(-hi, -lo)
+    } else {
+        let whole = floor(lo.clone());
+        let lo_frac = lo - Rational::from(whole.clone());
+        if lo_frac.is_zero() {
+            return Rational::from(whole);
+        }
+        let next = Rational::from(whole.clone() + 1);
+        if next <= hi {
+            return next;
+        }
+        // Both ends share the same integer part, so recurse on the reciprocals of the fractional
+        // parts. Taking the reciprocal swaps which end is the lower bound.
+        let hi_frac = hi - Rational::from(whole.clone());
+        let frac = simplest_between(hi_frac.invert(), lo_frac.invert());
+        Rational::from(whole) + frac.invert()
+    }
+}
+
+/// The floor of the `index`th root of the non-negative `num`.
+pub(crate) fn iroot(num: &Int, index: usize, interrupt: &Interrupt) -> Result<Int, CalcError> {
+    if *num < 2 || index == 1 {
+        return Ok(num.clone());
+    }
+    // Any root with an index at least the bit length of num is below 2.
+    if index >= num.bit_length() as usize {
+        return Ok(Int::one());
+    }
+    if index == 2 {
+        // sqrt_rem only fails for negative numbers.
+        return Ok(num.clone().sqrt_rem().unwrap().0);
+    }
+    // Newton's method, starting from a power of two that is at least the root. The iterates
+    // decrease monotonically until they reach the floor of the root.
+    let index_int = Int::from(index);
+    let mut root = Int::one() << (num.bit_length() as usize).div_ceil(index);
+    loop {
+        interrupt.check()?;
+        let next = (&root * Int::from(index - 1) + num / root.pow(index - 1)) / &index_int;
+        if next >= root {
+            return Ok(root);
+        }
+        root = next;
+    }
+}
+
+/// The `index`th root of `num`, or `None` if there is no real root.
+///
+/// The result is exact when `num` is the `index`th power of a rational, otherwise it's
+/// the simplest rational within 10^-precision of the true root.
+pub fn root(
+    num: Rational,
+    index: usize,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Option<Rational>, CalcError> {
+    let (num, den) = num.into_parts();
+    if index == 0 {
+        return Ok(None);
+    }
+    if num < 0 {
+        return if index.is_multiple_of(2) {
+            Ok(None)
+        } else {
+            let root = root(Rational::new(-num, den), index, precision, interrupt)?;
+            Ok(root.map(|root| -root))
+        };
+    }
+    let num_root = iroot(&num, index, interrupt)?;
+    let den_root = iroot(&den, index, interrupt)?;
+    if num_root.pow(index) == num && den_root.pow(index) == den {
+        return Ok(Some(Rational::new(num_root, den_root)));
+    }
+    // root(num / den) = root(num * den^(index - 1)) / den, and scaling by 10^precision before
+    // taking the integer root keeps that many decimal digits.
+    let scale = int_pow(&Int::from(10), precision, interrupt)?;
+    let scaled = num * int_pow(&den, index - 1, interrupt)? * int_pow(&scale, index, interrupt)?;
+    let root = iroot(&scaled, index, interrupt)?;
+    let den = den * scale;
+    let lo = Rational::new(root.clone(), den.clone());
+    let hi = Rational::new(root + 1, den);
+    Ok(Some(simplest_between(lo, hi)))
+}
+
+/// About the number of bits in the integer that [`root`] takes the `index`th root of to
+/// approximate the root of `num` to `precision` digits.
+pub(crate) fn root_bits(num: &Rational, index: usize, precision: usize) -> usize {
+    let (num, den) = num.clone().into_parts();
+    let (num, den) = (num.bit_length() as usize, den.bit_length() as usize);
+    // Each decimal digit of precision adds a little over 3.32 bits for each power of the index.
+    let digits = precision.saturating_mul(3322) / 1000;
+    let per_power = den.saturating_add(digits);
+    num.saturating_add(per_power.saturating_mul(index))
+}
+
+/// The square root of `num`, or `None` if it's negative.
+pub fn sqrt(
+    num: Rational,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Option<Rational>, CalcError> {
+    root(num, 2, precision, interrupt)
+}
+
+/// Mathematical constants that can be pushed onto the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Constant {
+    Pi,
+    E,
+    /// The golden ratio.
+    Phi,
+    Sqrt2,
+    Ln2,
+}
+
+/// `scale` times the sum of the series for `atan(1 / n)`, or `atanh(1 / n)` when it's not
+/// `alternating`, truncating each term.
+fn arctan_inv(
+    n: u32,
+    scale: &Int,
+    alternating: bool,
+    interrupt: &Interrupt,
+) -> Result<Int, CalcError> {
+    let n_squared = Int::from(n) * Int::from(n);
+    let mut power = scale / Int::from(n);
+    let mut sum = Int::zero();
+    let mut k = 0_u32;
+    while !power.is_zero() {
+        interrupt.check()?;
+        let term = &power / Int::from(2 * k + 1);
+        if alternating && k % 2 == 1 {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        power /= &n_squared;
+        k += 1;
+    }
+    Ok(sum)
+}
+
+impl Constant {
+    /// `scale` times the constant, give or take a few units for each term of the series.
+    pub(crate) fn fixed(self, scale: &Int, interrupt: &Interrupt) -> Result<Int, CalcError> {
+        Ok(match self {
+            // Machin's formula: pi = 16 atan(1/5) - 4 atan(1/239)
+            Constant::Pi => {
+                arctan_inv(5, scale, true, interrupt)? * Int::from(16)
+                    - arctan_inv(239, scale, true, interrupt)? * Int::from(4)
+            }
+            Constant::E => {
+                let (mut sum, mut term) = (Int::zero(), scale.clone());
+                let mut k = 1_u32;
+                while !term.is_zero() {
+                    interrupt.check()?;
+                    sum += &term;
+                    term /= Int::from(k);
+                    k += 1;
+                }
+                sum
+            }
+            Constant::Phi => {
+                let root = iroot(&(scale * scale * Int::from(5)), 2, interrupt)?;
+                (root + scale) / Int::from(2)
+            }
+            Constant::Sqrt2 => iroot(&(scale * scale * Int::from(2)), 2, interrupt)?,
+            // ln 2 = 2 atanh(1/3)
+            Constant::Ln2 => arctan_inv(3, scale, false, interrupt)? * Int::from(2),
+        })
+    }
+}
+
+/// The number of decimal digits that constants and functions are computed with to be accurate
+/// to `precision` digits.
+pub fn fixed_digits(precision: usize) -> usize {
+    // Each term of a series is truncated, so the last few digits may be off by about the number
+    // of terms. Computing extra digits keeps that error well below the precision.
+    precision + precision.to_string().len() + 4
+}
+
+/// Approximate `constant` to within 10^-(precision + 1), as a multiple of 10^-digits where
+/// `digits` is larger than the precision.
+///
+/// Pass the result to [`simplify`] to get a rational to put on the stack.
+pub fn constant(
+    constant: Constant,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Int, CalcError> {
+    let scale = int_pow(&Int::from(10), fixed_digits(precision), interrupt)?;
+    constant.fixed(&scale, interrupt)
+}
+
+/// Find the simplest rational within 10^-precision of the value approximated by `approx`, a
+/// multiple of 10^-[`fixed_digits`]`(accuracy)` that is within 10^-(accuracy + 1) of it.
+///
+/// `accuracy` must be at least `precision`.
+pub fn simplify(approx: &Int, accuracy: usize, precision: usize) -> Rational {
+    let ten = Int::from(10);
+    let digits = fixed_digits(accuracy);
+    let width = ten.pow(digits - precision) - ten.pow(digits - accuracy - 1);
+    let den = ten.pow(digits);
+    let lo = Rational::new(approx - &width, den.clone());
+    simplest_between(lo, Rational::new(approx + width, den))
+}
@@ -0,0 +1,131 @@
+//! The full screen view of the stack that `browse` opens in the REPL.
+//!
+//! Entries are listed top first and numbered the way the REPL numbers them. The selected entry
+//! is also shown in each base, and can be deleted or moved up and down the stack. Leaving with
+//! `q` or Enter keeps the changes, as one edit that `undo` can revert, and Esc throws them away.
+use rcalc::{Base, Calculator, Quantity};
+use std::io::{self, stdin, stdout, Write};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use termion::{clear, cursor, style, terminal_size};
+
+/// The keys, as shown at the top of the screen.
+const HELP: &str = "j/k select  J/K move  d delete  q keep changes  Esc discard them";
+
+/// The bases that the selected entry is shown in, below the list.
+const BASES: &[(&str, Base)] = &[
+    ("dec", Base::Decimal),
+    ("hex", Base::Hexadecimal),
+    ("oct", Base::Octal),
+    ("bin", Base::Binary),
+];
+
+/// Show the stack of `calculator` until the user leaves, then apply their changes.
+pub fn browse(calculator: &mut Calculator) -> io::Result<()> {
+    let mut entries: Vec<Quantity> = calculator.stack().iter().rev().cloned().collect();
+    let mut selected = 0;
+    let mut scroll = 0;
+    let keep = {
+        // Both the alternate screen and raw mode end when this is dropped.
+        let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+        let mut keys = stdin().keys();
+        loop {
+            draw(&mut screen, calculator, &entries, selected, &mut scroll)?;
+            let last = entries.len().saturating_sub(1);
+            match keys.next().transpose()? {
+                Some(Key::Up) | Some(Key::Char('k')) => selected = selected.saturating_sub(1),
+                Some(Key::Down) | Some(Key::Char('j')) => selected = (selected + 1).min(last),
+                Some(Key::Home) | Some(Key::Char('g')) => selected = 0,
+                Some(Key::End) | Some(Key::Char('G')) => selected = last,
+                // Toward the top of the stack, which is listed first.
+                Some(Key::Char('K')) if selected > 0 => {
+                    entries.swap(selected, selected - 1);
+                    selected -= 1;
+                }
+                Some(Key::Char('J')) if selected < last => {
+                    entries.swap(selected, selected + 1);
+                    selected += 1;
+                }
+                Some(Key::Char('d')) | Some(Key::Delete) if !entries.is_empty() => {
+                    entries.remove(selected);
+                    selected = selected.min(entries.len().saturating_sub(1));
+                }
+                Some(Key::Char('q')) | Some(Key::Char('\n')) => break true,
+                Some(Key::Esc) | Some(Key::Ctrl('c')) | None => break false,
+                Some(_) => (),
+            }
+        }
+    };
+    if keep {
+        entries.reverse();
+        calculator.replace_stack(entries);
+    }
+    Ok(())
+}
+
+/// Draw the help, the entries that fit on the screen and the selected entry in each base,
+/// scrolling so that the selected entry is on the screen.
+fn draw(
+    screen: &mut impl Write,
+    calculator: &Calculator,
+    entries: &[Quantity],
+    selected: usize,
+    scroll: &mut usize,
+) -> io::Result<()> {
+    let (width, height) = terminal_size()?;
+    let (width, height) = (usize::from(width), usize::from(height));
+    // The help, the list and a blank line above the selected entry in each base.
+    let rows = height.saturating_sub(2 + BASES.len()).max(1);
+    if selected < *scroll {
+        *scroll = selected;
+    } else if selected >= *scroll + rows {
+        *scroll = selected + 1 - rows;
+    }
+    let line = |row: usize| cursor::Goto(1, (row + 1) as u16);
+    let fit = |text: String| text.chars().take(width).collect::<String>();
+    write!(screen, "{}{}{}", clear::All, line(0), fit(HELP.to_string()))?;
+    if entries.is_empty() {
+        write!(screen, "{}The stack is empty", line(1))?;
+    }
+    let (formatter, settings) = (calculator.formatter(), calculator.settings());
+    for (row, (index, entry)) in entries
+        .iter()
+        .enumerate()
+        .skip(*scroll)
+        .take(rows)
+        .enumerate()
+    {
+        let text = fit(format!(
+            "{:>3}: {}",
+            index,
+            formatter.quantity(entry, settings)
+        ));
+        if index == selected {
+            write!(
+                screen,
+                "{}{}{}{}",
+                line(row + 1),
+                style::Invert,
+                text,
+                style::Reset
+            )?;
+        } else {
+            write!(screen, "{}{}", line(row + 1), text)?;
+        }
+    }
+    if let Some(entry) = entries.get(selected) {
+        for (row, (name, base)) in BASES.iter().enumerate() {
+            let text = formatter.with_base(*base).quantity(entry, settings);
+            let row = rows + 2 + row;
+            write!(
+                screen,
+                "{}{}",
+                line(row),
+                fit(format!("{}: {}", name, text))
+            )?;
+        }
+    }
+    screen.flush()
+}
@@ -0,0 +1,32 @@
+//! Copying text to the system clipboard.
+//!
+//! The standard library has no clipboard, so this uses `arboard` when the `clipboard` feature
+//! is on, which talks to the clipboard of each platform directly.
+use crate::error::CalcError;
+
+/// The clipboard, which is kept open once it has been used. On X11 the copied text is only
+/// there for as long as the program that copied it serves it.
+#[cfg(feature = "clipboard")]
+static CLIPBOARD: std::sync::Mutex<Option<arboard::Clipboard>> = std::sync::Mutex::new(None);
+
+/// Put `text` on the clipboard.
+#[cfg(feature = "clipboard")]
+pub fn copy(text: &str) -> Result<(), CalcError> {
+    let error =
+        |e: arboard::Error| CalcError::Io(format!("could not copy to the clipboard: {}", e));
+    // A panic while copying leaves the clipboard as usable as it was.
+    let mut clipboard = CLIPBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let clipboard = match &mut *clipboard {
+        Some(clipboard) => clipboard,
+        None => clipboard.insert(arboard::Clipboard::new().map_err(error)?),
+    };
+    clipboard.set_text(text).map_err(error)
+}
+
+/// Fail, as the clipboard needs the `clipboard` feature.
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_: &str) -> Result<(), CalcError> {
+    Err(CalcError::Io(
+        "Copying to the clipboard needs the clipboard feature".into(),
+    ))
+}
@@ -0,0 +1,456 @@
+//! The configuration file, `~/.config/rcalc/config.toml`.
+//!
+//! Only the parts of TOML that the configuration needs are understood: tables, and keys with
+//! string, integer, boolean or array values.
+//!
+//! The prompts may include segments that show the state of the calculator, updated after each
+//! line: `{depth}` for the number of entries on the stack, `{base}` for the output base,
+//! `{angle}` for the unit of angles and `{word}` for the word size.
+//!
+//! ```toml
+//! prompt = "[{base} w{word} d:{depth}] >> "
+//! continuation_prompt = ".. "
+//! base = "hex"
+//! precision = 50
+//! word_size = 32
+//! max_bits = 1000000 # or "unlimited"
+//! persist = true # keep the session in ~/.local/share/rcalc/state between runs of the REPL
+//! keymap = "vi" # or "emacs", the default
+//! startup = [": sq dup * ;"]
+//!
+//! [colors]
+//! prompt = "magenta"
+//! error = "light red" # or a 256 color index, or "none"
+//! ```
+use rcalc::{Calculator, Token};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use termion::color::AnsiValue;
+
+/// The names of the segments that prompts may include, in braces.
+const SEGMENTS: [&str; 4] = ["depth", "base", "angle", "word"];
+
+/// A value on the right of a `key = value` line.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn describe(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// Settings for the REPL and the calculator.
+pub struct Config {
+    /// The prompt, which may include segments such as `{depth}`.
+    pub prompt: String,
+    /// The prompt for the rest of a line that's continued with `\` or an unfinished definition.
+    pub continuation_prompt: String,
+    /// The color of the prompt, or `None` to leave it uncolored.
+    pub prompt_color: Option<AnsiValue>,
+    /// The color of errors, or `None` to leave them uncolored.
+    pub error_color: Option<AnsiValue>,
+    /// Lines evaluated before any other input, built from the modes and `startup` commands.
+    pub startup: Vec<String>,
+    /// Whether the REPL saves the session when it exits and restores it when it starts.
+    pub persist: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prompt: ">> ".to_string(),
+            continuation_prompt: ".. ".to_string(),
+            prompt_color: Some(AnsiValue(5)),
+            error_color: Some(AnsiValue(9)),
+            startup: Vec::new(),
+            persist: false,
+        }
+    }
+}
+
+/// Read the value that starts at the beginning of `from`, returning it and the rest of `from`.
+fn parse_value(from: &str) -> Result<(Value, &str), String> {
+    let from = from.trim_start();
+    if let Some(rest) = from.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(string), &rest[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, '"')) => string.push('"'),
+                    Some((_, '\\')) => string.push('\\'),
+                    _ => return Err("unsupported escape in string".to_string()),
+                },
+                c => string.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    } else if let Some(mut rest) = from.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    } else {
+        let end = from
+            .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+            .unwrap_or(from.len());
+        let (word, rest) = from.split_at(end);
+        match word {
+            "true" => Ok((Value::Boolean(true), rest)),
+            "false" => Ok((Value::Boolean(false), rest)),
+            _ => word
+                .replace('_', "")
+                .parse()
+                .map(|n| (Value::Integer(n), rest))
+                .map_err(|_| format!("unexpected value `{}`", word)),
+        }
+    }
+}
+
+/// Remove a `#` comment from the end of `line`, leaving any `#` inside of strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Whether `value` opens more arrays than it closes, leaving out brackets inside strings.
+fn unclosed(value: &str) -> bool {
+    let mut depth = 0_usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+/// Parse `text` into a map from dotted keys, such as `colors.prompt`, to their values.
+///
+/// Errors are prefixed with the line they were found on. Setting a key twice is an error.
+fn parse_table(text: &str) -> Result<HashMap<String, (usize, Value)>, String> {
+    let mut table = HashMap::new();
+    let mut prefix = String::new();
+    // Arrays may span several lines, so a key waits here until its value is complete.
+    let mut pending: Option<(usize, String, String)> = None;
+    for (line_offset, line) in text.lines().enumerate() {
+        let linum = line_offset + 1;
+        let line = strip_comment(line).trim();
+        let (start, key, value) = match pending.take() {
+            Some((start, key, value)) => (start, key, value + " " + line),
+            None if line.is_empty() => continue,
+            None if line.starts_with('[') => {
+                let name = line
+                    .strip_prefix('[')
+                    .and_then(|name| name.strip_suffix(']'))
+                    .ok_or_else(|| format!("{}: expected `]`", linum))?;
+                prefix = format!("{}.", name.trim());
+                continue;
+            }
+            None => {
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| format!("{}: expected `key = value`", linum))?;
+                (linum, prefix.clone() + key.trim(), value.trim().to_string())
+            }
+        };
+        match parse_value(&value) {
+            Ok((value, rest)) if rest.trim().is_empty() => {
+                if let Some((first, _)) = table.get(&key) {
+                    return Err(format!(
+                        "{}: `{}` is already set on line {}",
+                        start, key, first
+                    ));
+                }
+                table.insert(key, (start, value));
+            }
+            Ok(_) => return Err(format!("{}: unexpected characters after the value", linum)),
+            Err(_) if value.starts_with('[') && unclosed(&value) => {
+                pending = Some((start, key, value));
+            }
+            Err(message) => return Err(format!("{}: {}", linum, message)),
+        }
+    }
+    match pending {
+        Some((start, _, _)) => Err(format!("{}: unterminated array", start)),
+        None => Ok(table),
+    }
+}
+
+/// Check that every segment in the prompt `template` is one of [`SEGMENTS`].
+fn check_segments(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let end = rest.find('}').ok_or("unterminated segment in prompt")?;
+        if !SEGMENTS.contains(&&rest[..end]) {
+            return Err(format!("unknown prompt segment `{{{}}}`", &rest[..end]));
+        }
+        rest = &rest[end + 1..];
+    }
+    Ok(())
+}
+
+/// The value of the prompt segment `name` for the state of `calculator`.
+fn segment(name: &str, calculator: &Calculator) -> Option<String> {
+    let settings = calculator.settings();
+    Some(match name {
+        "depth" => calculator.stack().len().to_string(),
+        "base" => Token::Radix(calculator.formatter().base()).to_string(),
+        "angle" => Token::Angles(settings.angle()).to_string(),
+        "word" => match settings.word_size() {
+            Some(bits) => bits.to_string(),
+            None => "∞".to_string(),
+        },
+        _ => return None,
+    })
+}
+
+/// Fill in the segments of the prompt `template` from the state of `calculator`.
+pub fn expand(template: &str, calculator: &Calculator) -> String {
+    let mut prompt = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        prompt.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find('}').unwrap_or(rest.len());
+        match segment(&rest[..end], calculator) {
+            Some(value) => {
+                prompt.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => prompt.push('{'),
+        }
+    }
+    prompt.push_str(rest);
+    prompt
+}
+
+/// Parse a color name, such as `light red`, an ANSI color number, or `none` for no color.
+fn parse_color(value: &Value) -> Result<Option<AnsiValue>, String> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    match value {
+        Value::Integer(n) if (0..=255).contains(n) => Ok(Some(AnsiValue(*n as u8))),
+        Value::String(name) if name == "none" => Ok(None),
+        Value::String(name) => {
+            let name = name.to_lowercase().replace(['_', '-'], " ");
+            let (bright, base) = match name.strip_prefix("light ") {
+                Some(base) => (8, base),
+                None => (0, name.as_str()),
+            };
+            NAMES
+                .iter()
+                .position(|color| *color == base)
+                .map(|n| Some(AnsiValue(n as u8 + bright)))
+                .ok_or_else(|| format!("unknown color `{}`", name))
+        }
+        _ => Err("expected a color name or a number from 0 to 255".to_string()),
+    }
+}
+
+impl Config {
+    /// Read the configuration from `path`, or the defaults if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}:{}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut table: Vec<_> = parse_table(text)?.into_iter().collect();
+        // Keys are handled in the order they're written, so the first error is the one reported,
+        // except that modes are set before startup commands run.
+        table.sort_by_key(|(key, (linum, _))| (key == "startup", *linum));
+        for (key, (linum, value)) in table {
+            let expected = |what: &str| format!("{}: `{}` must be {}", linum, key, what);
+            match (key.as_str(), &value) {
+                ("prompt", Value::String(prompt)) => {
+                    check_segments(prompt).map_err(|e| format!("{}: {}", linum, e))?;
+                    config.prompt = prompt.clone()
+                }
+                ("continuation_prompt", Value::String(prompt)) => {
+                    check_segments(prompt).map_err(|e| format!("{}: {}", linum, e))?;
+                    config.continuation_prompt = prompt.clone()
+                }
+                ("base", Value::String(base)) => match base.as_str() {
+                    "bin" | "oct" | "dec" | "hex" => config.startup.push(base.clone()),
+                    _ => return Err(expected("one of \"bin\", \"oct\", \"dec\" or \"hex\"")),
+                },
+                ("precision", Value::Integer(digits)) if *digits >= 0 => {
+                    config.startup.push(format!("prec {}", digits))
+                }
+                ("word_size", Value::Integer(bits @ (8 | 16 | 32 | 64))) => {
+                    config.startup.push(format!("word {}", bits))
+                }
+                ("word_size", Value::String(size)) if size == "unlimited" => {
+                    config.startup.push("word unlimited".to_string())
+                }
+                ("max_bits", Value::Integer(bits)) if *bits > 0 => {
+                    config.startup.push(format!("maxbits {}", bits))
+                }
+                ("max_bits", Value::String(size)) if size == "unlimited" => {
+                    config.startup.push("maxbits unlimited".to_string())
+                }
+                ("persist", Value::Boolean(persist)) => config.persist = *persist,
+                ("keymap", Value::String(keymap)) => match keymap.as_str() {
+                    "vi" | "emacs" => config.startup.push(format!("set keymap {}", keymap)),
+                    _ => return Err(expected("\"vi\" or \"emacs\"")),
+                },
+                ("startup", Value::Array(lines)) => {
+                    for line in lines {
+                        match line {
+                            Value::String(line) => config.startup.push(line.clone()),
+                            _ => return Err(expected("an array of strings")),
+                        }
+                    }
+                }
+                ("colors.prompt", color) => {
+                    config.prompt_color =
+                        parse_color(color).map_err(|e| format!("{}: {}", linum, e))?
+                }
+                ("colors.error", color) => {
+                    config.error_color =
+                        parse_color(color).map_err(|e| format!("{}: {}", linum, e))?
+                }
+                ("prompt", _) => return Err(expected("a string")),
+                ("continuation_prompt", _) => return Err(expected("a string")),
+                ("base", _) => return Err(expected("a string")),
+                ("precision", _) => return Err(expected("a non-negative integer")),
+                ("word_size", _) => return Err(expected("8, 16, 32, 64 or \"unlimited\"")),
+                ("max_bits", _) => return Err(expected("a positive integer or \"unlimited\"")),
+                ("persist", _) => return Err(expected("a boolean")),
+                ("keymap", _) => return Err(expected("a string")),
+                ("startup", _) => return Err(expected("an array of strings")),
+                _ => {
+                    return Err(format!(
+                        "{}: unknown key `{}` with {}",
+                        linum,
+                        key,
+                        value.describe()
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let table = parse_table(
+            "a = \"x # y\" # comment\nb = 1_000\nc = false\n[colors]\nprompt = [1, [\"]\"]]",
+        )
+        .unwrap();
+        assert_eq!(table["a"], (1, Value::String("x # y".to_string())));
+        assert_eq!(table["b"], (2, Value::Integer(1000)));
+        assert_eq!(table["c"], (3, Value::Boolean(false)));
+        let array = Value::Array(vec![
+            Value::Integer(1),
+            Value::Array(vec![Value::String("]".to_string())]),
+        ]);
+        assert_eq!(table["colors.prompt"], (5, array));
+    }
+
+    #[test]
+    fn multi_line_arrays() {
+        let table =
+            parse_table("startup = [\"[x]\",\n  \"y ]\", # ]\n  \"z\"\n]\nbase = \"hex\"").unwrap();
+        let lines = ["[x]", "y ]", "z"].map(|line| Value::String(line.to_string()));
+        assert_eq!(table["startup"], (1, Value::Array(lines.to_vec())));
+        assert_eq!(table["base"], (5, Value::String("hex".to_string())));
+        assert_eq!(
+            parse_table("\nstartup = [\"[x]\",\n").unwrap_err(),
+            "2: unterminated array"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_table("a = 1\n\na = 2").unwrap_err(),
+            "3: `a` is already set on line 1"
+        );
+        assert!(parse_table("[colors]\nprompt = 1\n[colors]\nprompt = 2").is_err());
+        assert!(parse_table("prompt = 1\n[colors]\nprompt = 2").is_ok());
+        assert_eq!(
+            parse_table("a = 1 2").unwrap_err(),
+            "1: unexpected characters after the value"
+        );
+        assert_eq!(parse_table("a").unwrap_err(), "1: expected `key = value`");
+        assert_eq!(
+            parse_table("a = \"b").unwrap_err(),
+            "1: unterminated string"
+        );
+    }
+
+    #[test]
+    fn config() {
+        let config = Config::parse(
+            "startup = [\"1 2\"]\nprecision = 10\npersist = true\n[colors]\nerror = \"none\"",
+        )
+        .unwrap();
+        assert_eq!(config.startup, ["prec 10", "1 2"]);
+        assert!(config.persist);
+        assert!(config.error_color.is_none());
+        assert_eq!(
+            Config::parse("precision = \"ten\"").err().unwrap(),
+            "1: `precision` must be a non-negative integer"
+        );
+        assert_eq!(
+            Config::parse("word_size = 12").err().unwrap(),
+            "1: `word_size` must be 8, 16, 32, 64 or \"unlimited\""
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! Calendar dates, written as `2024-03-01`.
+//!
+//! Dates are in the proleptic Gregorian calendar from the year 1 to 9999, and are stored as
+//! the number of days since 1970-01-01. Subtracting two dates gives the number of days between
+//! them, and adding a number of days or a duration to a date shifts it.
+use crate::error::{CalcError, TokenError};
+use crate::number::Rational;
+use alloc::format;
+use alloc::string::String;
+use num_traits::One;
+
+/// The days from 1970-01-01 to 0001-01-01 and to 9999-12-31.
+const FIRST_DAY: i64 = -719_162;
+const LAST_DAY: i64 = 2_932_896;
+
+/// The number of days from 1970-01-01 to the given day.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Counting years from March puts the leap day at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year, month and day of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Whether `from` is written like a date, with a `-` after the year and the month.
+pub(crate) fn is_date(from: &str) -> bool {
+    let bytes = from.as_bytes();
+    bytes.len() == 10 && bytes[4] == b'-' && bytes[7] == b'-'
+}
+
+/// Parse a date written as `YYYY-MM-DD` into days since 1970-01-01.
+pub(crate) fn parse(from: &str) -> Result<Rational, TokenError> {
+    let field = |range: core::ops::Range<usize>| {
+        let digits = &from[range.clone()];
+        match digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => Ok(digits.parse::<u32>().unwrap()),
+            false => Err(TokenError {
+                error: CalcError::InvalidDate,
+                span: range,
+            }),
+        }
+    };
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let year = i64::from(year);
+    if year == 0 {
+        return Err(TokenError {
+            error: CalcError::InvalidDate,
+            span: 0..4,
+        });
+    }
+    if month == 0 || month > 12 {
+        return Err(TokenError {
+            error: CalcError::InvalidDate,
+            span: 5..7,
+        });
+    }
+    if day == 0 || day > days_in_month(year, month) {
+        return Err(TokenError {
+            error: CalcError::InvalidDate,
+            span: 8..10,
+        });
+    }
+    Ok(Rational::from(days_from_civil(year, month, day)))
+}
+
+/// Shift the date `days` since 1970-01-01 by `by` days, which must be a whole number.
+pub(crate) fn shift(days: Rational, by: Rational) -> Result<Rational, CalcError> {
+    if !by.clone().into_parts().1.is_one() {
+        return Err(CalcError::NonIntegerDays);
+    }
+    let days = days + by;
+    if days < Rational::from(FIRST_DAY) || days > Rational::from(LAST_DAY) {
+        return Err(CalcError::DateOutOfRange);
+    }
+    Ok(days)
+}
+
+/// Write the date `days` since 1970-01-01 as `YYYY-MM-DD`.
+pub(crate) fn format(days: &Rational) -> String {
+    // Dates are only made by parsing and shifting, which keep them whole and in range.
+    let days = i64::from(&days.clone().into_parts().0);
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
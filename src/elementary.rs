@@ -0,0 +1,555 @@
+//! Elementary functions of rationals: trigonometry, logarithms and exponentials.
+//!
+//! Each function is evaluated in fixed point, as an integer multiple of 10^-digits where
+//! `digits` is a little larger than the precision, and the result is the simplest rational
+//! within 10^-precision of the true value. Rational results, such as `0 sin` or `60 cos` in
+//! degrees, come out exactly.
+use crate::approx::{self, fixed_digits, iroot, Constant};
+use crate::error::CalcError;
+use crate::number::{Int, Rational};
+use crate::primes::next_prime;
+use crate::{floor, int_pow, modulo, Interrupt};
+use alloc::string::ToString;
+use num_traits::{One, Zero};
+
+/// The unit that the trigonometric functions measure angles in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Angle {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+/// Fixed point arithmetic accurate to a precision.
+struct Fixed<'a> {
+    /// The number of decimal digits that the result must be accurate to.
+    precision: usize,
+    /// Fixed point numbers are integer multiples of `1 / scale`.
+    scale: Int,
+    /// Checked by each step of a series, which can take a long time at a high precision.
+    interrupt: &'a Interrupt,
+}
+
+impl<'a> Fixed<'a> {
+    fn new(precision: usize, interrupt: &'a Interrupt) -> Result<Self, CalcError> {
+        Ok(Fixed {
+            precision,
+            scale: int_pow(&Int::from(10), fixed_digits(precision), interrupt)?,
+            interrupt,
+        })
+    }
+
+    /// `num` in fixed point, rounded down.
+    fn from(&self, num: &Rational) -> Int {
+        floor(num.clone() * Rational::from(self.scale.clone()))
+    }
+
+    fn mul(&self, lhs: &Int, rhs: &Int) -> Int {
+        lhs * rhs / &self.scale
+    }
+
+    fn div(&self, lhs: &Int, rhs: &Int) -> Int {
+        lhs * &self.scale / rhs
+    }
+
+    /// The square root of the non-negative `num`, rounded down.
+    fn sqrt(&self, num: &Rational) -> Int {
+        let squared = floor(num.clone() * Rational::from(&self.scale * &self.scale));
+        // sqrt_rem only fails for negative numbers.
+        squared.sqrt_rem().unwrap().0
+    }
+
+    fn pi(&self) -> Result<Int, CalcError> {
+        Constant::Pi.fixed(&self.scale, self.interrupt)
+    }
+
+    /// The simplest rational within 10^-precision of `num`.
+    fn simplify(&self, num: &Int) -> Rational {
+        approx::simplify(num, self.precision, self.precision)
+    }
+
+    /// Reduce the angle `x` to about -pi..=pi radians.
+    fn reduce(&self, x: &Rational, angle: Angle) -> Result<Int, CalcError> {
+        Ok(match angle {
+            Angle::Degrees => {
+                // Whole turns are removed exactly, before converting to radians.
+                let mut x = modulo(x.clone(), Rational::from(360));
+                if x > Rational::from(180) {
+                    x -= Rational::from(360);
+                }
+                self.from(&x) * self.pi()? / (Int::from(180) * &self.scale)
+            }
+            Angle::Radians => {
+                // Multiplying pi by the number of turns multiplies its error too, so both the
+                // turns and the remainder are found with as many extra digits as x has whole
+                // digits.
+                let whole = floor(x.clone().abs());
+                let extra = Int::from(10).pow(whole.to_string().len());
+                let scale = &self.scale * &extra;
+                let x = floor(x.clone() * Rational::from(scale.clone()));
+                let pi = Constant::Pi.fixed(&scale, self.interrupt)?;
+                let turns = floor(Rational::new(&x + &pi, &pi * Int::from(2)));
+                let reduced = (x - turns * pi * Int::from(2)) / extra;
+                debug_assert!(reduced.clone().abs() <= self.pi()? * Int::from(2));
+                reduced
+            }
+        })
+    }
+
+    /// The sine, or the cosine if `cos` is set, of `x` radians by its Taylor series.
+    fn sin_cos(&self, x: &Int, cos: bool) -> Result<Int, CalcError> {
+        let x_squared = self.mul(x, x);
+        let (mut term, mut n) = if cos {
+            (self.scale.clone(), 0_u32)
+        } else {
+            (x.clone(), 1_u32)
+        };
+        let mut sum = Int::zero();
+        while !term.is_zero() {
+            self.interrupt.check()?;
+            sum += &term;
+            term = -self.mul(&term, &x_squared) / Int::from((n + 1) * (n + 2));
+            n += 2;
+        }
+        Ok(sum)
+    }
+
+    /// The arctangent of `x`, which must be between -1 and 1.
+    fn atan_small(&self, x: &Int) -> Result<Int, CalcError> {
+        // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), and applying that twice brings x within
+        // tan(pi/16), where the series converges quickly.
+        let mut x = x.clone();
+        for _ in 0..2 {
+            let root = (&self.scale * &self.scale + &x * &x).sqrt_rem().unwrap().0;
+            x = self.div(&x, &(&self.scale + root));
+        }
+        Ok(self.arctan_series(&x, true)? * Int::from(4))
+    }
+
+    /// The sum of the series for `atan(x)`, or `atanh(x)` when it's not `alternating`.
+    fn arctan_series(&self, x: &Int, alternating: bool) -> Result<Int, CalcError> {
+        let x_squared = self.mul(x, x);
+        let (mut power, mut sum) = (x.clone(), Int::zero());
+        let mut k = 0_u32;
+        while !power.is_zero() {
+            self.interrupt.check()?;
+            let term = &power / Int::from(2 * k + 1);
+            if alternating && k % 2 == 1 {
+                sum -= term;
+            } else {
+                sum += term;
+            }
+            power = self.mul(&power, &x_squared);
+            k += 1;
+        }
+        Ok(sum)
+    }
+
+    /// The arctangent of `x`, in radians.
+    fn atan(&self, x: &Rational) -> Result<Int, CalcError> {
+        if *x < Rational::zero() {
+            Ok(-self.atan(&-x.clone())?)
+        } else if *x > Rational::one() {
+            // atan(x) = pi/2 - atan(1/x)
+            Ok(self.pi()? / Int::from(2) - self.atan_small(&self.from(&x.clone().invert()))?)
+        } else {
+            self.atan_small(&self.from(x))
+        }
+    }
+
+    /// The arcsine of `x`, in radians.
+    fn asin(&self, x: &Rational) -> Result<Int, CalcError> {
+        if x.clone().abs() > Rational::one() {
+            return Err(CalcError::OutOfDomain);
+        }
+        if *x < Rational::zero() {
+            return self.asin(&-x.clone()).map(|asin| -asin);
+        }
+        let squared = x.clone() * x.clone();
+        let rest = self.sqrt(&(Rational::one() - squared.clone()));
+        let x = self.from(x);
+        // asin(x) = atan(x / sqrt(1 - x^2)), but the reciprocal is better behaved near 1.
+        if squared <= Rational::new(Int::one(), Int::from(2)) {
+            self.atan_small(&self.div(&x, &rest))
+        } else {
+            Ok(self.pi()? / Int::from(2) - self.atan_small(&self.div(&rest, &x))?)
+        }
+    }
+
+    /// The natural logarithm of the positive `x`.
+    fn ln(&self, x: &Rational) -> Result<Int, CalcError> {
+        // ln(x) = k ln(2) + ln(m), where m = x / 2^k is between 1/2 and 2. Then
+        // ln(m) = 2 atanh((m - 1) / (m + 1)), and that series converges quickly.
+        let (num, den) = x.clone().into_parts();
+        let k = i64::from(num.bit_length()) - i64::from(den.bit_length());
+        let shift = k.unsigned_abs() as usize;
+        let m = if k >= 0 {
+            Rational::new(num, den << shift)
+        } else {
+            Rational::new(num << shift, den)
+        };
+        let one = Rational::one();
+        let y = self.from(&((m.clone() - one.clone()) / (m + one)));
+        let ln_m = self.arctan_series(&y, false)? * Int::from(2);
+        if k == 0 {
+            return Ok(ln_m);
+        }
+        // As when reducing angles, ln(2) needs extra digits to be multiplied by k.
+        let extra = Int::from(10).pow(k.unsigned_abs().to_string().len());
+        let ln_2 = Constant::Ln2.fixed(&(&self.scale * &extra), self.interrupt)?;
+        Ok(ln_m + Int::from(k) * ln_2 / extra)
+    }
+
+    /// e to the power of `x`, which must be between -1 and 1.
+    fn exp_small(&self, x: &Int) -> Result<Int, CalcError> {
+        let (mut term, mut sum) = (self.scale.clone(), Int::zero());
+        let mut n = 1_u32;
+        while !term.is_zero() {
+            self.interrupt.check()?;
+            sum += &term;
+            term = self.mul(&term, x) / Int::from(n);
+            n += 1;
+        }
+        Ok(sum)
+    }
+
+    /// Convert `radians` to `angle` and simplify it.
+    fn angle(&self, radians: Int, angle: Angle) -> Result<Rational, CalcError> {
+        let num = match angle {
+            Angle::Radians => radians,
+            Angle::Degrees => radians * Int::from(180) * &self.scale / self.pi()?,
+        };
+        Ok(self.simplify(&num))
+    }
+}
+
+/// Find `num / den` to within 10^-precision, where `terms` computes `num` and `den` in fixed
+/// point.
+///
+/// Dividing magnifies the errors in `num` and `den` when `den` is close to zero or `num` is
+/// large, so more digits are computed until they're small enough. `den` must not be zero.
+fn quotient(
+    precision: usize,
+    interrupt: &Interrupt,
+    terms: impl Fn(&Fixed) -> Result<(Int, Int), CalcError>,
+) -> Result<Rational, CalcError> {
+    let mut accuracy = precision + 3;
+    loop {
+        let fixed = Fixed::new(accuracy, interrupt)?;
+        let (num, den) = terms(&fixed)?;
+        let digits = fixed_digits(accuracy);
+        let length = |n: &Int| n.clone().abs().to_string().len();
+        let leading_zeros = (digits + 1).saturating_sub(length(&den));
+        let whole_digits = length(&num).saturating_sub(digits);
+        let needed = precision + 2 * leading_zeros + whole_digits + 3;
+        if needed > accuracy {
+            accuracy = needed;
+            continue;
+        }
+        let excess = Int::from(10).pow(digits - fixed_digits(precision));
+        return Ok(approx::simplify(
+            &(fixed.div(&num, &den) / excess),
+            precision,
+            precision,
+        ));
+    }
+}
+
+/// The sine of the angle `x`.
+pub fn sin(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    let fixed = Fixed::new(precision, interrupt)?;
+    Ok(fixed.simplify(&fixed.sin_cos(&fixed.reduce(&x, angle)?, false)?))
+}
+
+/// The cosine of the angle `x`.
+pub fn cos(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    let fixed = Fixed::new(precision, interrupt)?;
+    Ok(fixed.simplify(&fixed.sin_cos(&fixed.reduce(&x, angle)?, true)?))
+}
+
+/// The tangent of the angle `x`, which is undefined at odd multiples of a right angle.
+pub fn tan(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    if angle == Angle::Degrees {
+        let (quarters, den) = (x.clone() / Rational::from(90)).into_parts();
+        if den.is_one() && !(quarters % Int::from(2)).is_zero() {
+            return Err(CalcError::OutOfDomain);
+        }
+    }
+    quotient(precision, interrupt, |fixed| {
+        let x = fixed.reduce(&x, angle)?;
+        Ok((fixed.sin_cos(&x, false)?, fixed.sin_cos(&x, true)?))
+    })
+}
+
+/// The arcsine of `x`, as an angle.
+pub fn asin(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    let fixed = Fixed::new(precision, interrupt)?;
+    fixed.angle(fixed.asin(&x)?, angle)
+}
+
+/// The arccosine of `x`, as an angle.
+pub fn acos(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    let fixed = Fixed::new(precision, interrupt)?;
+    let asin = fixed.asin(&x)?;
+    fixed.angle(fixed.pi()? / Int::from(2) - asin, angle)
+}
+
+/// The arctangent of `x`, as an angle.
+pub fn atan(
+    x: Rational,
+    angle: Angle,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    let fixed = Fixed::new(precision, interrupt)?;
+    fixed.angle(fixed.atan(&x)?, angle)
+}
+
+/// Write the positive `x` as `root^power`, with the smallest possible `root`.
+///
+/// Only prime indices are tried, as a root with a composite index is found as roots with each
+/// of its prime factors in turn.
+fn perfect_power(mut x: Rational, interrupt: &Interrupt) -> Result<(Rational, usize), CalcError> {
+    let mut power = 1;
+    let mut index = 2;
+    loop {
+        interrupt.check()?;
+        let (num, den) = x.clone().into_parts();
+        if index > core::cmp::max(num.bit_length(), den.bit_length()) as usize {
+            return Ok((x, power));
+        }
+        let (num_root, den_root) = (
+            iroot(&num, index, interrupt)?,
+            iroot(&den, index, interrupt)?,
+        );
+        if num_root.pow(index) == num && den_root.pow(index) == den {
+            x = Rational::new(num_root, den_root);
+            power *= index;
+        } else {
+            index = usize::from(&next_prime(&Int::from(index), interrupt)?);
+        }
+    }
+}
+
+/// `log_base(x)`, if it's rational.
+///
+/// That's only the case when `x` and `base` are both powers of the same number, so the result
+/// is the ratio of those powers.
+fn exact_log(
+    x: &Rational,
+    base: &Rational,
+    interrupt: &Interrupt,
+) -> Result<Option<Rational>, CalcError> {
+    let one = Rational::one();
+    let (mut root, power) = perfect_power(base.clone(), interrupt)?;
+    let mut power = Int::from(power);
+    if root < one {
+        root = root.invert();
+        power = -power;
+    }
+    let mut x = x.clone();
+    if x < one {
+        x = x.invert();
+        power = -power;
+    }
+    x.normalize();
+    root.normalize();
+    // The root is greater than one, so its numerator is too, and x is a power of the root when
+    // its numerator and denominator are the same powers of the root's.
+    let (mut num, den) = x.into_parts();
+    let (root_num, root_den) = root.into_parts();
+    let mut count = 0;
+    while num > 1 && (&num % &root_num).is_zero() {
+        interrupt.check()?;
+        num /= &root_num;
+        count += 1;
+    }
+    if num == 1 && den == int_pow(&root_den, count, interrupt)? {
+        Ok(Some(Rational::new(Int::from(count), power)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The natural logarithm of `x`.
+pub fn ln(x: Rational, precision: usize, interrupt: &Interrupt) -> Result<Rational, CalcError> {
+    if x <= Rational::zero() {
+        return Err(CalcError::OutOfDomain);
+    }
+    let fixed = Fixed::new(precision, interrupt)?;
+    Ok(fixed.simplify(&fixed.ln(&x)?))
+}
+
+/// The logarithm of `x` to `base`, which is exact when it's rational.
+pub fn log(
+    x: Rational,
+    base: Rational,
+    precision: usize,
+    interrupt: &Interrupt,
+) -> Result<Rational, CalcError> {
+    if x <= Rational::zero() || base <= Rational::zero() || base == Rational::one() {
+        return Err(CalcError::OutOfDomain);
+    }
+    if let Some(log) = exact_log(&x, &base, interrupt)? {
+        return Ok(log);
+    }
+    quotient(precision, interrupt, |fixed| {
+        Ok((fixed.ln(&x)?, fixed.ln(&base)?))
+    })
+}
+
+/// The largest power that `exp` accepts; e to this power already has over 40,000 digits.
+const MAX_EXP: u32 = 100_000;
+
+/// e to the power of `x`.
+pub fn exp(x: Rational, precision: usize, interrupt: &Interrupt) -> Result<Rational, CalcError> {
+    if x > Rational::from(MAX_EXP) {
+        return Err(CalcError::ExponentTooLarge);
+    }
+    // e^-3 is less than 1/10, so anything smaller than this is closer to zero than the precision.
+    if x < Int::from(precision + 2) * Int::from(-3) {
+        return Ok(Rational::zero());
+    }
+    // e^x = 2^k e^r, where k is the nearest integer to x / ln(2), so r = x - k ln(2) is small.
+    let rough = Fixed::new(0, interrupt)?;
+    let ln_2 = Constant::Ln2.fixed(&rough.scale, interrupt)?;
+    let ln_2 = Rational::new(ln_2, rough.scale.clone());
+    let k = floor(x.clone() / ln_2 + Rational::new(Int::one(), Int::from(2)));
+    // Multiplying by 2^k multiplies the error by as much, so compute that many more digits.
+    let extra_digits = if k > 0 {
+        usize::from(&k) * 30_103 / 100_000 + 1
+    } else {
+        0
+    };
+    let accuracy = precision + extra_digits;
+    let fixed = Fixed::new(accuracy, interrupt)?;
+    let extra = Int::from(10).pow(k.clone().abs().to_string().len());
+    let ln_2 = Constant::Ln2.fixed(&(&fixed.scale * &extra), interrupt)?;
+    let r = fixed.from(&x) - &k * ln_2 / extra;
+    let exp_r = fixed.exp_small(&r)?;
+    let exp = if k < 0 {
+        exp_r >> usize::from(&-k)
+    } else {
+        exp_r << usize::from(&k)
+    };
+    let excess = Int::from(10).pow(fixed_digits(accuracy) - fixed_digits(precision));
+    Ok(approx::simplify(&(exp / excess), precision, precision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    /// Whether `x` is within 10^-18 of the decimal `expected`.
+    fn near(x: Rational, expected: &str) -> bool {
+        let (negative, digits) = match expected.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, expected),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap();
+        let num: Int = format!("{}{}", whole, fraction).parse().unwrap();
+        let mut expected = Rational::new(num, Int::from(10).pow(fraction.len()));
+        if negative {
+            expected = -expected;
+        }
+        (x - expected).abs() < Rational::new(Int::one(), Int::from(10).pow(18))
+    }
+
+    fn power_of_ten(exponent: usize) -> Rational {
+        Rational::from(Int::from(10).pow(exponent))
+    }
+
+    type Trig = fn(Rational, Angle, usize, &Interrupt) -> Result<Rational, CalcError>;
+
+    /// The trigonometric function `f` of `x` in `angle` units, to 20 digits.
+    fn trig(f: Trig, x: Rational, angle: Angle) -> Rational {
+        f(x, angle, 20, &Interrupt::default()).unwrap()
+    }
+
+    #[test]
+    fn small_angles() {
+        let sin_1 = trig(sin, Rational::one(), Angle::Radians);
+        assert!(near(sin_1, "0.84147098480789650665"));
+        let cos_3 = trig(cos, Rational::from(3), Angle::Radians);
+        assert!(near(cos_3, "-0.98999249660044545727"));
+        assert_eq!(
+            trig(sin, Rational::from(30), Angle::Degrees),
+            Rational::new(Int::one(), Int::from(2))
+        );
+    }
+
+    #[test]
+    fn large_angles() {
+        let x = power_of_ten(22);
+        assert!(near(
+            trig(sin, x.clone(), Angle::Radians),
+            "-0.85220084976718880177"
+        ));
+        assert!(near(
+            trig(cos, x.clone(), Angle::Radians),
+            "0.52321478539513894550"
+        ));
+        assert!(near(
+            trig(sin, -x, Angle::Radians),
+            "0.85220084976718880177"
+        ));
+        let x = power_of_ten(45);
+        assert!(near(
+            trig(sin, x.clone(), Angle::Radians),
+            "-0.99820270392505935767"
+        ));
+        assert!(near(
+            trig(tan, x, Angle::Radians),
+            "16.65670744141990428284"
+        ));
+        assert!(near(
+            trig(cos, power_of_ten(50), Angle::Radians),
+            "-0.61352860823366356226"
+        ));
+        let x = power_of_ten(60) + Rational::from(7);
+        assert!(near(trig(sin, x, Angle::Radians), "0.25997098594853697209"));
+    }
+
+    #[test]
+    fn exact_logs() {
+        let log = |x: Rational, base: Rational| log(x, base, 20, &Interrupt::default()).unwrap();
+        let power = |base: i64, exp: usize| Rational::from(Int::from(base).pow(exp));
+        let ratio = |num: i64, den: i64| Rational::new(Int::from(num), Int::from(den));
+        assert_eq!(log(Rational::from(8), Rational::from(4)), ratio(3, 2));
+        assert_eq!(log(ratio(1, 27), Rational::from(9)), ratio(-3, 2));
+        assert_eq!(log(ratio(64, 729), ratio(4, 9)), Rational::from(3));
+        // 2^36 is found by taking square and cube roots in turn.
+        assert_eq!(log(power(2, 60), power(2, 36)), ratio(5, 3));
+        assert_eq!(log(power(3, 1500), power(3, 1000)), ratio(3, 2));
+        assert!(near(
+            log(Rational::from(10), Rational::from(2)),
+            "3.32192809488736234787"
+        ));
+    }
+}
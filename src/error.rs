@@ -0,0 +1,340 @@
+//! Errors found while lexing and evaluating input.
+use crate::units::MAX_POWER;
+use crate::MAX_FACTORIAL;
+use alloc::string::String;
+use core::fmt::{self, Display, Formatter};
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Everything that can go wrong with a line of input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    /// A token with no characters.
+    EmptyToken,
+    /// A word that isn't an operator, number or name.
+    UnexpectedToken,
+    /// An operator followed by more characters.
+    TrailingChars,
+    /// A number with an invalid digit.
+    InvalidInteger(crate::number::ParseIntError),
+    /// A `radix#digits` literal with a radix outside of 2 to 36.
+    InvalidRadix,
+    /// A `radix#digits` literal without any digits.
+    MissingDigits,
+    /// A digit that isn't valid in the radix of its literal.
+    InvalidDigit(u32),
+    /// A `_` digit separator that isn't between two digits.
+    MisplacedSeparator,
+    /// A decimal with an invalid digit after the point.
+    InvalidFraction,
+    /// A malformed exponent in scientific notation.
+    InvalidExponent(core::num::ParseIntError),
+    /// A name containing something other than letters, digits or underscores.
+    InvalidName,
+    /// A `=` without a name after it.
+    MissingVariableName,
+    /// A `=` followed by an operator or number instead of a name.
+    ExpectedVariableName,
+    /// The argument of `word` isn't 8, 16, 32, 64 or `unlimited`.
+    InvalidWordSize,
+    /// The argument of `maxbits` isn't a positive number or `unlimited`.
+    InvalidBitLimit,
+    /// The argument of `prec` isn't a number of digits.
+    InvalidPrecision,
+    /// A `seed` that isn't a 64 bit unsigned integer.
+    InvalidSeed,
+    /// A register number that is out of range.
+    InvalidRegister,
+    /// The argument of `show` isn't `top`, `all` or `changed`.
+    InvalidShow,
+    /// The argument of `order` isn't `top` or `bottom`.
+    InvalidOrder,
+    /// The argument of `trace` or `lenient` isn't `on` or `off`.
+    InvalidToggle,
+    /// The first argument of `set` isn't the name of a setting.
+    UnknownSetting,
+    /// The value of `set keymap` isn't `vi` or `emacs`.
+    InvalidKeymap,
+    /// The argument of `group` isn't a single punctuation character or `off`.
+    InvalidSeparator,
+    /// A unit with an unknown name or a malformed power.
+    InvalidUnit,
+    /// A command at the end of the line without its argument.
+    MissingArgument,
+    /// A `(` comment without a closing `)`.
+    UnterminatedComment,
+    /// An infix expression without a `)` for each `(`.
+    UnbalancedParens,
+    /// An infix expression with something other than a number, name or `(` where one of those
+    /// belongs.
+    ExpectedOperand,
+    /// An infix expression with something other than an operator or `)` after an operand.
+    ExpectedOperator,
+    /// An infix expression nested within too many parentheses, signs or powers.
+    ExpressionTooDeep,
+    /// A `:` that isn't followed by the name of the new word.
+    ExpectedWordName,
+    /// A `:` inside of a definition.
+    NestedDefinition,
+    /// A `:` without a matching `;`.
+    UnterminatedDefinition,
+    /// A `;` without a matching `:`.
+    UnexpectedEndDefinition,
+    /// An `if` without a matching `then`.
+    UnterminatedConditional,
+    /// An `else` or `then` without a matching `if`.
+    UnexpectedBranch,
+    /// A `do` without a matching `loop`.
+    UnterminatedLoop,
+    /// A `loop` without a matching `do`.
+    UnexpectedLoop,
+    /// An `if` or `do` nested within too many other blocks.
+    NestedTooDeep,
+    /// A `do` count that isn't a non-negative integer.
+    InvalidLoopCount,
+    /// An `i` that isn't inside of a `do` loop.
+    NotInLoop,
+    /// Evaluation would pop more entries than the stack holds.
+    StackUnderflow,
+    /// A `pick` or `roll` index that isn't a non-negative integer.
+    InvalidStackIndex,
+    /// A `pick` or `roll` index past the bottom of the stack.
+    StackIndexTooDeep,
+    /// A modulus that isn't a positive integer.
+    InvalidModulus,
+    /// An `invmod` of a number that shares a factor with the modulus.
+    NoInverse,
+    /// A `powmod` with a negative exponent.
+    NegativeExponent,
+    /// A `prevprime` of an integer no greater than 2.
+    NoPreviousPrime,
+    /// A result with more bits than the limit set with `maxbits`.
+    ResultTooLarge(usize),
+    /// A `factor` of an integer less than 2.
+    InvalidFactor,
+    /// A line stopped by [`interrupt`](crate::interrupt), usually from Ctrl-C.
+    Interrupted,
+    /// An `approx` bound that isn't a positive integer.
+    InvalidDenominator,
+    /// A `randint` whose lower bound is above its upper bound.
+    EmptyRange,
+    /// A `[a0;a1,a2,...]` literal with a term that isn't a whole number, or is 0 after the first.
+    InvalidContinuedFraction,
+    /// A `YYYY-MM-DD` literal that isn't a date from the year 1 to 9999.
+    InvalidDate,
+    /// An IPv4 address that isn't four numbers from 0 to 255.
+    InvalidAddress,
+    /// A subnet prefix length that isn't an integer from 0 to 32.
+    InvalidPrefix,
+    /// An operation that doesn't apply to dates.
+    DateArithmetic,
+    /// Shifting a date by a fraction of a day.
+    NonIntegerDays,
+    /// Shifting a date before the year 1 or after 9999.
+    DateOutOfRange,
+    /// An `ans` after a line that left the stack empty.
+    NoAnswer,
+    /// A `$` that isn't followed by a positive integer.
+    InvalidResult,
+    /// A `$n` for a result that hasn't been recorded.
+    NoResult(usize),
+    /// A bit count that is infinite without a word size.
+    NeedsWordSize,
+    /// A byte swap of a word size that isn't a whole number of bytes.
+    PartialBytes,
+    /// A statistic of fewer entries than it's defined for.
+    TooFewEntries(usize),
+    /// Division, remainder or reciprocal of zero.
+    DivisionByZero,
+    /// An exponent with a fractional part.
+    NonIntegerExponent,
+    /// An exponent too large to compute.
+    ExponentTooLarge,
+    /// An operand with a fractional part where an integer is required.
+    NotAnInteger,
+    /// An operand that must be a non-negative integer, but isn't.
+    NotANaturalNumber,
+    /// A shift count that isn't a non-negative integer.
+    InvalidShift,
+    /// A bit index that is negative, fractional or outside the word.
+    InvalidBit,
+    /// A range of bits with its high bit below its low bit.
+    InvalidBitRange,
+    /// A `fact` operand larger than the supported limit.
+    FactorialTooLarge,
+    /// A `choose` or `perm` that would need too many terms.
+    TooManyItems,
+    /// An operand that a function, such as `asin`, isn't defined for.
+    OutOfDomain,
+    /// An even root, such as `sqrt`, of a negative number.
+    NegativeRoot,
+    /// A root index that isn't a positive integer.
+    InvalidRootIndex,
+    /// An `undo` without an earlier line to revert.
+    NothingToUndo,
+    /// A `redo` without an `undo` to reapply.
+    NothingToRedo,
+    /// Adding, subtracting or converting quantities with different dimensions.
+    IncompatibleUnits,
+    /// A quantity with a unit where a plain number is required.
+    UnexpectedUnit,
+    /// A quantity with a dimension raised beyond the largest power allowed.
+    UnitPowerTooLarge,
+    /// A session file that couldn't be read or written.
+    Io(String),
+    /// A session file with an error on one of its lines.
+    InvalidSession(String),
+    /// A name that isn't a user defined word or variable.
+    Undefined(String),
+    /// A user defined word that calls itself too deeply.
+    RecursionTooDeep(String),
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use CalcError::*;
+        match self {
+            EmptyToken => f.write_str("unexpected empty token"),
+            UnexpectedToken => f.write_str("unexpected token"),
+            TrailingChars => f.write_str("Unexpected trailing characters"),
+            InvalidInteger(e) => e.fmt(f),
+            InvalidRadix => f.write_str("radix must be from 2 to 36"),
+            MissingDigits => f.write_str("missing digits after `#`"),
+            InvalidDigit(radix) => write!(f, "invalid digit for base {}", radix),
+            MisplacedSeparator => f.write_str("`_` must be between two digits"),
+            InvalidFraction => f.write_str("invalid digit found in fraction"),
+            InvalidExponent(e) => e.fmt(f),
+            InvalidName => f.write_str("unexpected character in name"),
+            MissingVariableName => f.write_str("missing variable name"),
+            ExpectedVariableName => f.write_str("expected a variable name"),
+            InvalidWordSize => f.write_str("word size must be 8, 16, 32, 64 or `unlimited`"),
+            InvalidBitLimit => {
+                f.write_str("bit limit must be a positive number of bits or `unlimited`")
+            }
+            InvalidPrecision => f.write_str("precision must be a non-negative number of digits"),
+            InvalidSeed => f.write_str("seed must be an integer from 0 to 2^64-1"),
+            InvalidRegister => write!(f, "register must be from 0 to {}", crate::REGISTERS - 1),
+            InvalidShow => f.write_str("expected `top`, `all` or `changed`"),
+            InvalidOrder => f.write_str("expected `top` or `bottom`"),
+            InvalidToggle => f.write_str("expected `on` or `off`"),
+            UnknownSetting => f.write_str("expected `keymap`"),
+            InvalidKeymap => f.write_str("expected `vi` or `emacs`"),
+            InvalidSeparator => f.write_str("expected a punctuation character or `off`"),
+            InvalidUnit => f.write_str("unknown unit"),
+            MissingArgument => f.write_str("missing argument"),
+            UnterminatedComment => f.write_str("unterminated comment"),
+            UnbalancedParens => f.write_str("missing `)`"),
+            ExpectedOperand => f.write_str("expected a number, name or `(`"),
+            ExpectedOperator => f.write_str("expected an operator or `)`"),
+            ExpressionTooDeep => f.write_str("too many parentheses or operators nested together"),
+            ExpectedWordName => f.write_str("Expected the name of the new word"),
+            NestedDefinition => f.write_str("Word definitions can not be nested"),
+            UnterminatedDefinition => f.write_str("Missing `;` to end the definition"),
+            UnexpectedEndDefinition => f.write_str("Unexpected `;` outside of a definition"),
+            UnterminatedConditional => f.write_str("Missing `then` to end the `if`"),
+            UnexpectedBranch => f.write_str("Unexpected `else` or `then` outside of an `if`"),
+            UnterminatedLoop => f.write_str("Missing `loop` to end the `do`"),
+            UnexpectedLoop => f.write_str("Unexpected `loop` outside of a `do`"),
+            NestedTooDeep => f.write_str("Too many `if` or `do` blocks nested within each other"),
+            InvalidLoopCount => f.write_str("Loop count must be a non-negative integer"),
+            NotInLoop => f.write_str("`i` can only be used inside of a loop"),
+            StackUnderflow => {
+                f.write_str("Stack exhaustion would have occured during evaluation; aborting")
+            }
+            InvalidStackIndex => f.write_str("Stack index must be a non-negative integer"),
+            StackIndexTooDeep => f.write_str("Stack index is deeper than the stack"),
+            InvalidModulus => f.write_str("Modulus must be a positive integer"),
+            NoInverse => {
+                f.write_str("No inverse exists, as the number shares a factor with the modulus")
+            }
+            NegativeExponent => f.write_str("Exponent must not be negative"),
+            NoPreviousPrime => f.write_str("There is no prime below 2"),
+            ResultTooLarge(limit) => {
+                write!(
+                    f,
+                    "Result would be over the limit of {} bits, raise it with `maxbits`",
+                    limit
+                )
+            }
+            InvalidFactor => f.write_str("Can only factor integers greater than 1"),
+            Interrupted => f.write_str("Interrupted"),
+            InvalidDenominator => f.write_str("Maximum denominator must be a positive integer"),
+            EmptyRange => f.write_str("The lower bound must not be greater than the upper bound"),
+            InvalidDate => f.write_str("invalid date"),
+            InvalidContinuedFraction => f.write_str(
+                "expected a continued fraction of positive integers, as in `[3;7,15,1]`",
+            ),
+            InvalidAddress => f.write_str("IPv4 addresses are four numbers from 0 to 255"),
+            InvalidPrefix => f.write_str("Prefix length must be an integer from 0 to 32"),
+            DateArithmetic => {
+                f.write_str("Dates can only be compared, subtracted or shifted by days")
+            }
+            NonIntegerDays => f.write_str("Dates can only be shifted by whole days"),
+            DateOutOfRange => f.write_str("Date is outside of the years 1 to 9999"),
+            NoAnswer => f.write_str("The last line didn't leave a result"),
+            InvalidResult => f.write_str("Results are numbered from 1, as in `$1`"),
+            NoResult(index) => write!(f, "There is no result ${} yet", index),
+            NeedsWordSize => f.write_str("Needs a word size, set with `word`"),
+            PartialBytes => f.write_str("Word size must be a multiple of 8 bits"),
+            TooFewEntries(count) => write!(f, "Needs at least {} entries", count),
+            DivisionByZero => f.write_str("Division by zero"),
+            NonIntegerExponent => f.write_str("Exponent must be an integer"),
+            ExponentTooLarge => f.write_str("Exponent is too large"),
+            NotAnInteger => f.write_str("Operand must be an integer"),
+            NotANaturalNumber => f.write_str("Operands must be non-negative integers"),
+            InvalidShift => f.write_str("Shift count must be a non-negative integer"),
+            InvalidBit => f.write_str("Bit index must be a non-negative integer within the word"),
+            InvalidBitRange => f.write_str("High bit must not be below the low bit"),
+            FactorialTooLarge => write!(f, "Factorial operand must be at most {}", MAX_FACTORIAL),
+            TooManyItems => write!(
+                f,
+                "Number of items chosen must be at most {}",
+                MAX_FACTORIAL
+            ),
+            OutOfDomain => f.write_str("Operand is outside of the function's domain"),
+            NegativeRoot => f.write_str("Even root of a negative number"),
+            InvalidRootIndex => f.write_str("Root index must be a positive integer"),
+            NothingToUndo => f.write_str("Nothing to undo"),
+            NothingToRedo => f.write_str("Nothing to redo"),
+            IncompatibleUnits => f.write_str("Units have different dimensions"),
+            UnexpectedUnit => f.write_str("Operand must not have a unit"),
+            UnitPowerTooLarge => write!(f, "Unit powers must be from -{0} to {0}", MAX_POWER),
+            Io(message) | InvalidSession(message) => f.write_str(message),
+            Undefined(name) => write!(f, "Undefined word or variable `{}`", name),
+            RecursionTooDeep(name) => write!(f, "`{}` recursed too deeply", name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CalcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalcError::InvalidInteger(e) => Some(e),
+            CalcError::InvalidExponent(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An error, along with the span of the input line that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenError {
+    pub error: CalcError,
+    /// The byte range of the offending input.
+    pub span: Range<usize>,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for TokenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -0,0 +1,312 @@
+//! Rendering stack entries for display.
+use crate::number::{Int, Rational};
+use crate::{Quantity, Settings};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use num_traits::One;
+
+/// The bases that the stack can be displayed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Base {
+    Binary,
+    Octal,
+    #[default]
+    Decimal,
+    Hexadecimal,
+}
+
+impl Base {
+    /// The number of distinct digits in this base.
+    pub fn radix(self) -> u32 {
+        match self {
+            Base::Binary => 2,
+            Base::Octal => 8,
+            Base::Decimal => 10,
+            Base::Hexadecimal => 16,
+        }
+    }
+}
+
+/// How rationals that aren't integers are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// As an exact fraction, such as `1/3`.
+    #[default]
+    Exact,
+    /// As a positional expansion to the current precision, such as `0.333…`.
+    Expansion,
+    /// As a decimal expansion between 1 and 1000 times a power of 10 that is a multiple of 3,
+    /// such as `12.5e3`. Integers are written this way too.
+    Engineering,
+}
+
+/// Which stack entries are printed after each line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Show {
+    /// Only the top of the stack.
+    Top,
+    /// The whole stack.
+    #[default]
+    All,
+    /// The entries that the last line pushed or modified.
+    Changed,
+}
+
+/// Which end of the stack is printed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    BottomFirst,
+    TopFirst,
+}
+
+/// Renders stack entries for display.
+#[derive(Debug, Clone, Default)]
+pub struct Formatter {
+    pub(crate) base: Base,
+    pub(crate) notation: Notation,
+    pub(crate) show: Show,
+    pub(crate) order: Order,
+    pub(crate) separator: Option<char>,
+}
+
+impl Formatter {
+    /// The base that numbers are displayed in.
+    pub fn base(&self) -> Base {
+        self.base
+    }
+
+    /// How rationals that aren't integers are written.
+    pub fn notation(&self) -> Notation {
+        self.notation
+    }
+
+    /// Which stack entries are printed after each line.
+    pub fn show(&self) -> Show {
+        self.show
+    }
+
+    /// Which end of the stack is printed first.
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
+    /// What decimal digits are grouped in threes with, if anything.
+    pub fn separator(&self) -> Option<char> {
+        self.separator
+    }
+
+    /// A copy of this formatter that displays numbers in `base`.
+    pub fn with_base(&self, base: Base) -> Formatter {
+        Formatter {
+            base,
+            ..self.clone()
+        }
+    }
+
+    /// The digits of `num` in the current base, with a prefix such as `0x` outside of decimal.
+    fn digits(&self, num: &Int) -> String {
+        match self.base {
+            Base::Binary => format!("{:#b}", num),
+            Base::Octal => format!("{:#o}", num),
+            Base::Decimal => match self.separator {
+                Some(separator) => {
+                    let digits = num.clone().abs().to_string();
+                    let mut grouped = String::new();
+                    for (i, digit) in digits.chars().enumerate() {
+                        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                            grouped.push(separator);
+                        }
+                        grouped.push(digit);
+                    }
+                    let sign = if *num < 0 { "-" } else { "" };
+                    format!("{}{}", sign, grouped)
+                }
+                None => num.to_string(),
+            },
+            Base::Hexadecimal => format!("{:#x}", num),
+        }
+    }
+
+    /// Write `num / den` with up to `precision` digits after the point. A repeating expansion
+    /// that fits is written with the digits that repeat in parentheses, as in `0.1(6)`, and
+    /// otherwise it's followed by `…` if it was truncated.
+    fn expansion(&self, num: Int, den: &Int, precision: usize) -> String {
+        let radix = self.base.radix();
+        let sign = if num < 0 { "-" } else { "" };
+        let (whole, mut remainder) = num.abs().divmod(den);
+        let mut fraction = String::new();
+        // The digits after a remainder only depend on it, so they repeat once it does.
+        let mut seen = BTreeMap::new();
+        while remainder != 0 {
+            if let Some(&start) = seen.get(&remainder) {
+                fraction.insert(start, '(');
+                fraction.push(')');
+                remainder = Int::zero();
+                break;
+            }
+            if fraction.len() >= precision {
+                break;
+            }
+            seen.insert(remainder.clone(), fraction.len());
+            let (digit, rest) = (remainder * Int::from(radix)).divmod(den);
+            // The digit is less than the radix, so it's always valid.
+            fraction.push(core::char::from_digit(u32::from(&digit), radix).unwrap());
+            remainder = rest;
+        }
+        if remainder != 0 {
+            fraction.push('…');
+        }
+        let point = if precision == 0 { "" } else { "." };
+        format!("{}{}{}{}", sign, self.digits(&whole), point, fraction)
+    }
+
+    /// Write `num / den` in engineering notation, with `precision` digits after the point.
+    fn engineering(&self, num: Int, den: &Int, precision: usize) -> String {
+        if num == 0 {
+            return "0".to_string();
+        }
+        let sign = if num < 0 { "-" } else { "" };
+        let num = num.abs();
+        let power = |exponent: i64| Int::from(10).pow(exponent.unsigned_abs() as usize);
+        // Whether num / den is at least 10^exponent.
+        let at_least = |exponent: i64| match exponent {
+            0.. => num >= den * power(exponent),
+            _ => &num * power(exponent) >= *den,
+        };
+        // Counting digits finds the exponent to within one.
+        let mut exponent = num.to_string().len() as i64 - den.to_string().len() as i64;
+        while !at_least(exponent) {
+            exponent -= 1;
+        }
+        while at_least(exponent + 1) {
+            exponent += 1;
+        }
+        let exponent = exponent.div_euclid(3) * 3;
+        let mantissa = match exponent {
+            0.. => self.expansion(num, &(den * power(exponent)), precision),
+            _ => self.expansion(num * power(exponent), den, precision),
+        };
+        let mantissa = mantissa.trim_end_matches('.');
+        match exponent {
+            0 => format!("{}{}", sign, mantissa),
+            _ => format!("{}{}e{}", sign, mantissa, exponent),
+        }
+    }
+
+    /// Render `num` for display.
+    ///
+    /// Outside of decimal, negative integers that fit the word show their two's complement bit
+    /// pattern when a word size is set, and engineering notation falls back to expansions.
+    /// Everything else is shown in full, even when it's too big for the word.
+    pub fn format(&self, num: &Rational, settings: &Settings) -> String {
+        let (num, den) = num.clone().into_parts();
+        if self.notation == Notation::Engineering && self.base == Base::Decimal {
+            return self.engineering(num, &den, settings.precision());
+        }
+        if den.is_one() {
+            match self.bit_pattern(&num, settings) {
+                Some(pattern) => self.digits(&pattern),
+                None => self.digits(&num),
+            }
+        } else {
+            match self.notation {
+                Notation::Exact => format!("{}/{}", self.digits(&num), self.digits(&den)),
+                Notation::Expansion | Notation::Engineering => {
+                    self.expansion(num, &den, settings.precision())
+                }
+            }
+        }
+    }
+
+    /// Render `quantity` for display, followed by its unit.
+    pub fn quantity(&self, quantity: &Quantity, settings: &Settings) -> String {
+        if let Some(date) = quantity.as_date() {
+            return date;
+        }
+        let num = self.format(quantity.value(), settings);
+        match quantity.unit() {
+            Some(unit) => format!("{} {}", num, unit.name()),
+            None => num,
+        }
+    }
+
+    /// The two's complement bit pattern shown for `num`, if it's a negative integer that fits
+    /// the word outside of decimal.
+    fn bit_pattern(&self, num: &Int, settings: &Settings) -> Option<Int> {
+        if self.base == Base::Decimal || settings.word_size.is_none() || *num >= 0 {
+            return None;
+        }
+        if settings.wrap_signed(num.clone()) == *num {
+            Some(settings.wrap_unsigned(num.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// The signed decimal value of a negative integer whose two's complement bit pattern is
+    /// displayed. Positive numbers are left alone, even when their top bit is set.
+    fn signed(&self, quantity: &Quantity, settings: &Settings) -> Option<String> {
+        if quantity.date {
+            return None;
+        }
+        let (num, den) = quantity.value().clone().into_parts();
+        if den.is_one() && self.bit_pattern(&num, settings).is_some() {
+            Some(num.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Render the entries of `stack` from `start` up, one line each.
+    ///
+    /// Each entry is numbered by its depth, which is the index that `pick` and `roll` use, and
+    /// the top of the stack is marked with `>`. Negative integers shown as a bit pattern are
+    /// followed by their signed decimal value.
+    pub fn render(&self, stack: &[Quantity], start: usize, settings: &Settings) -> Vec<String> {
+        let width = stack.len().saturating_sub(1).to_string().len();
+        let mut lines: Vec<String> = stack[start..]
+            .iter()
+            .enumerate()
+            .map(|(offset, num)| {
+                let depth = stack.len() - 1 - (start + offset);
+                let marker = if depth == 0 { '>' } else { ' ' };
+                let signed = self.signed(num, settings);
+                let num = match signed {
+                    Some(signed) => format!("{} ({})", self.quantity(num, settings), signed),
+                    None => self.quantity(num, settings),
+                };
+                format!("{} {:>width$}: {}", marker, depth, num, width = width)
+            })
+            .collect();
+        if self.order == Order::TopFirst {
+            lines.reverse();
+        }
+        lines
+    }
+}
+
+/// Render the low `width` bits of `num` in groups of four, above a ruler that numbers the highest
+/// bit of each group and bit 0.
+pub fn bits(num: &Int, width: u32) -> String {
+    let mut bits = String::new();
+    let mut ruler = String::new();
+    for index in (0..width).rev() {
+        if index == width - 1 || index % 4 == 3 || index == 0 {
+            // A label that would run into the one before is left out.
+            if ruler.len() < bits.len() || ruler.is_empty() {
+                while ruler.len() < bits.len() {
+                    ruler.push(' ');
+                }
+                ruler.push_str(&index.to_string());
+            }
+        }
+        bits.push(if num.bit(index) { '1' } else { '0' });
+        if index % 4 == 0 && index != 0 {
+            bits.push(' ');
+        }
+    }
+    format!("{}\n{}", bits, ruler)
+}
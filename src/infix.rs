@@ -0,0 +1,195 @@
+//! Infix expressions, written as `=( 1 + 2 * sqrt(x) )`.
+//!
+//! The expression is compiled into the tokens that compute it in Reverse Polish Notation. It
+//! may use numbers, `+`, `-`, `*`, `/`, `%` and `^` with the usual precedence, parentheses, and
+//! names. A name followed by parentheses is an operator applied to its comma separated
+//! arguments, as in `max(a, b)`, and any other name is a constant, variable or word.
+use crate::error::{CalcError, TokenError};
+use crate::Token::{self, *};
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Compile the infix expression `text` into tokens, with literals of at most `max_bits` bits.
+pub(crate) fn compile(text: &str, max_bits: Option<usize>) -> Result<Vec<Token>, TokenError> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        tokens: Vec::new(),
+        max_bits,
+        depth: 0,
+    };
+    parser.expression(0)?;
+    parser.skip_space();
+    if parser.pos < text.len() {
+        return Err(parser.error(CalcError::ExpectedOperator));
+    }
+    Ok(parser.tokens)
+}
+
+/// The operator for `c` between two operands, and how tightly it binds to its left and right.
+fn binary(c: char) -> Option<(Token, u8, u8)> {
+    match c {
+        '+' => Some((Plus, 1, 2)),
+        '-' => Some((Minus, 1, 2)),
+        '*' => Some((Times, 3, 4)),
+        '/' => Some((Divide, 3, 4)),
+        '%' => Some((Modulo, 3, 4)),
+        // Binding more tightly on the left makes `^` right associative.
+        '^' => Some((Exp, 8, 7)),
+        _ => None,
+    }
+}
+
+/// How tightly a leading `-` binds, which is more than `*` but less than `^`, so that `-2^2`
+/// is -4.
+const PREFIX: u8 = 5;
+
+/// How deeply parentheses, arguments, signs and `^` may nest before the expression is rejected.
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    tokens: Vec<Token>,
+    /// The most bits that a literal may have.
+    max_bits: Option<usize>,
+    /// How many expressions the current position is nested within.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// An error at the current position, or at the `)` that ends the expression.
+    fn error(&self, error: CalcError) -> TokenError {
+        let len = self.peek().map_or(1, char::len_utf8);
+        TokenError {
+            error,
+            span: self.pos..(self.pos + len),
+        }
+    }
+
+    /// Take the characters from the current position for as long as `keep` accepts them.
+    fn take(&mut self, keep: impl Fn(&str, char) -> bool) -> (&str, usize) {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !keep(&self.text[start..self.pos], c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        (&self.text[start..self.pos], start)
+    }
+
+    /// Lex `word` as a single token, reporting errors where it is in the expression.
+    fn push_word(&mut self, word: &str, start: usize) -> Result<(), TokenError> {
+        let token = Token::read(word, self.max_bits).map_err(|e| TokenError {
+            span: (e.span.start + start)..(e.span.end + start),
+            ..e
+        })?;
+        self.tokens.push(token);
+        Ok(())
+    }
+
+    /// Compile an expression made of operators that bind more tightly than `min_binding`.
+    fn expression(&mut self, min_binding: u8) -> Result<(), TokenError> {
+        self.skip_space();
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(CalcError::ExpressionTooDeep));
+        }
+        self.depth += 1;
+        self.operand()?;
+        loop {
+            self.skip_space();
+            let (operator, left, right) = match self.peek().and_then(binary) {
+                Some(operator) => operator,
+                None => break,
+            };
+            if left < min_binding {
+                break;
+            }
+            self.pos += 1;
+            self.expression(right)?;
+            self.tokens.push(operator);
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn operand(&mut self) -> Result<(), TokenError> {
+        self.skip_space();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                self.expression(0)?;
+                self.close()
+            }
+            Some('-') => {
+                self.pos += 1;
+                self.expression(PREFIX)?;
+                self.tokens.push(Negate);
+                Ok(())
+            }
+            Some('+') => {
+                self.pos += 1;
+                self.expression(PREFIX)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                // Literals run up to the next operator, except for the sign of an exponent.
+                let (literal, start) = self.take(|taken, c| {
+                    let exponent = taken.ends_with(['e', 'E']) && !taken.starts_with("0x");
+                    c.is_alphanumeric() || "._#".contains(c) || (exponent && "+-".contains(c))
+                });
+                let literal = literal.to_string();
+                self.push_word(&literal, start)
+            }
+            Some(c) if c.is_alphabetic() => {
+                let (name, start) = self.take(|_, c| c.is_alphanumeric() || c == '_');
+                let name = name.to_string();
+                self.skip_space();
+                if self.peek() == Some('(') {
+                    self.arguments()?;
+                }
+                self.push_word(&name, start)
+            }
+            _ => Err(self.error(CalcError::ExpectedOperand)),
+        }
+    }
+
+    /// Compile the parenthesized, comma separated arguments of an operator.
+    fn arguments(&mut self) -> Result<(), TokenError> {
+        self.pos += 1;
+        self.skip_space();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            self.expression(0)?;
+            self.skip_space();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                _ => return self.close(),
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), TokenError> {
+        self.skip_space();
+        match self.peek() {
+            Some(')') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.error(CalcError::ExpectedOperator)),
+            None => Err(self.error(CalcError::UnbalancedParens)),
+        }
+    }
+}
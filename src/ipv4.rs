@@ -0,0 +1,100 @@
+//! IPv4 addresses and subnets, written as `192.168.1.10` or `192.168.1.10/24`.
+//!
+//! Addresses are integers from 0 to 2^32 - 1 on the stack, and a subnet is an address followed
+//! by its prefix length, so the subnet operators take both from the stack.
+use crate::error::{CalcError, TokenError};
+use crate::number::{Int, Rational};
+use alloc::format;
+use alloc::string::String;
+use num_traits::One;
+
+/// Whether `from` is written like an address, as four numbers separated by `.`.
+pub(crate) fn is_address(from: &str) -> bool {
+    let address = from.split('/').next().unwrap_or(from);
+    address.matches('.').count() == 3 && address.bytes().all(|b| b == b'.' || b.is_ascii_digit())
+}
+
+/// Parse an address with an optional prefix length.
+pub(crate) fn parse(from: &str) -> Result<(u32, Option<u32>), TokenError> {
+    let (address, prefix) = match from.find('/') {
+        Some(slash) => (&from[..slash], Some(slash + 1)),
+        None => (from, None),
+    };
+    let mut start = 0;
+    let mut value = 0;
+    for octet in address.split('.') {
+        let end = start + octet.len();
+        match octet.parse::<u8>() {
+            Ok(octet) => value = value << 8 | u32::from(octet),
+            Err(_) => {
+                return Err(TokenError {
+                    error: CalcError::InvalidAddress,
+                    span: start..end.max(start + 1),
+                })
+            }
+        }
+        start = end + 1;
+    }
+    let prefix = match prefix {
+        Some(start) => match from[start..].parse::<u32>() {
+            Ok(prefix) if prefix <= 32 => Some(prefix),
+            _ => {
+                return Err(TokenError {
+                    error: CalcError::InvalidPrefix,
+                    span: start..from.len().max(start + 1),
+                })
+            }
+        },
+        None => None,
+    };
+    Ok((value, prefix))
+}
+
+/// The address that `num` is, which must be an integer that fits in 32 bits.
+pub(crate) fn to_address(num: &Rational) -> Result<u32, CalcError> {
+    let (num, den) = num.clone().into_parts();
+    if den.is_one() && num >= 0 && num <= Int::from(u32::MAX) {
+        Ok(u32::from(&num))
+    } else {
+        Err(CalcError::InvalidAddress)
+    }
+}
+
+/// The prefix length that `num` is, which must be an integer from 0 to 32.
+pub(crate) fn to_prefix(num: &Rational) -> Result<u32, CalcError> {
+    match to_address(num) {
+        Ok(prefix) if prefix <= 32 => Ok(prefix),
+        _ => Err(CalcError::InvalidPrefix),
+    }
+}
+
+/// The bits of an address that are the network, rather than the host.
+fn mask(prefix: u32) -> u32 {
+    u32::MAX.checked_shl(32 - prefix).unwrap_or(0)
+}
+
+/// The first address of the subnet holding `address`.
+pub(crate) fn network(address: u32, prefix: u32) -> u32 {
+    address & mask(prefix)
+}
+
+/// The last address of the subnet holding `address`.
+pub(crate) fn broadcast(address: u32, prefix: u32) -> u32 {
+    address | !mask(prefix)
+}
+
+/// The number of hosts in a subnet, leaving out the network and broadcast addresses except in
+/// the point-to-point subnets of /31 and /32.
+pub(crate) fn hosts(prefix: u32) -> u64 {
+    match prefix {
+        31 => 2,
+        32 => 1,
+        _ => (1 << (32 - prefix)) - 2,
+    }
+}
+
+/// Write `address` as four numbers separated by `.`.
+pub(crate) fn format(address: u32) -> String {
+    let [a, b, c, d] = address.to_be_bytes();
+    format!("{}.{}.{}.{}", a, b, c, d)
+}
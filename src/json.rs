@@ -0,0 +1,78 @@
+//! JSON output, for `--output json` and the WebAssembly interface.
+//!
+//! Each line of input produces one JSON value on its own line: the resulting stack as an array
+//! of `{"num": "3", "den": "4"}` objects, bottom first, with a `"unit"` such as `"m/s"` for
+//! quantities and a `"date"` such as `"2024-03-01"` for dates, whose numbers count the days
+//! since 1970-01-01, or an error object such as
+//! `{"error": "Division by zero", "location": "-e 1", "input": "1 0 /", "start": 4, "end": 5}`.
+//! Numerators and denominators are strings, since they may be too large for a JSON number to
+//! hold exactly. Text from commands like `help` is written as a JSON string.
+use crate::{Quantity, TokenError};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Quote and escape `text` as a JSON string.
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Writing to a String can't fail.
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The entries of `stack`, bottom first.
+pub fn stack(stack: &[Quantity]) -> String {
+    let entries: Vec<String> = stack
+        .iter()
+        .map(|quantity| {
+            let (num, den) = quantity.value().clone().into_parts();
+            if let Some(date) = quantity.as_date() {
+                return format!(
+                    r#"{{"num": "{}", "den": "{}", "date": {}}}"#,
+                    num,
+                    den,
+                    string(&date)
+                );
+            }
+            match quantity.unit() {
+                Some(unit) => format!(
+                    r#"{{"num": "{}", "den": "{}", "unit": {}}}"#,
+                    num,
+                    den,
+                    string(unit.name())
+                ),
+                None => format!(r#"{{"num": "{}", "den": "{}"}}"#, num, den),
+            }
+        })
+        .collect();
+    format!("[{}]", entries.join(", "))
+}
+
+/// An error in `input`, from the line named `location`.
+///
+/// `start` and `end` are the byte offsets of the span of `input` that caused the error.
+pub fn error(location: &str, input: &str, error: &TokenError) -> String {
+    format!(
+        r#"{{"error": {}, "location": {}, "input": {}, "start": {}, "end": {}}}"#,
+        string(&error.to_string()),
+        string(location),
+        string(input),
+        error.span.start,
+        error.span.end,
+    )
+}
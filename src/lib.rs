@@ -0,0 +1,3716 @@
+//! A Reverse Polish Notation, multiple precision calculator.
+//!
+//! Input lines are split into whitespace separated [`Token`]s, which a
+//! [`Calculator`] evaluates against its stack of exact rationals, each of which may have a
+//! [`Unit`].
+//!
+//! ```
+//! let mut calculator = rcalc::Calculator::default();
+//! assert!(calculator.parse("2 3 + 4 *").is_ok());
+//! assert_eq!(calculator.stack(), &[rcalc::Rational::from(20)]);
+//! ```
+//!
+//! Without the `std` feature the library is `no_std`, and needs only an allocator. Reading and
+//! writing files, the clipboard and seeding the random numbers from the system are left out.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::{self, Display, Formatter as FmtFormatter};
+use core::ops::Range;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use num_traits::{One, Zero};
+use number::RandomInt;
+pub use number::{Int, Rational};
+use operators::{Syntax, OPERATORS};
+use rand::rngs::StdRng;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use rand::FromEntropy;
+use rand::SeedableRng;
+#[cfg(feature = "std")]
+use std::fs;
+use Token::*;
+
+mod approx;
+#[cfg(feature = "std")]
+mod clipboard;
+mod date;
+mod elementary;
+mod error;
+mod format;
+mod infix;
+mod ipv4;
+pub mod json;
+mod number;
+mod operators;
+mod primes;
+mod stats;
+mod units;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+mod wasm;
+
+pub use approx::Constant;
+pub use elementary::Angle;
+pub use error::{CalcError, TokenError};
+pub use format::{Base, Formatter, Notation, Order, Show};
+pub use stats::Statistic;
+pub use units::{Quantity, Unit};
+
+/// Readable tokens from command line
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Number(Rational),
+    Measure(Quantity),
+    Const(Constant),
+    Minus,
+    Plus,
+    Times,
+    Divide,
+    Modulo,
+    Exp,
+    Gcd,
+    Lcm,
+    PowerMod,
+    InverseMod,
+    IsPrime,
+    NextPrime,
+    PreviousPrime,
+    Factor,
+    And,
+    Or,
+    Xor,
+    Not,
+    ShiftLeft,
+    ShiftRight,
+    BitSet,
+    BitClear,
+    BitToggle,
+    BitTest,
+    Mask,
+    MaskRange,
+    BitExtract,
+    BitInsert,
+    PopCount,
+    LeadingZeros,
+    TrailingZeros,
+    /// Reverse the bytes of a word this many bits wide, or of the word size.
+    ByteSwap(Option<u32>),
+    Duplicate,
+    Drop,
+    Swap,
+    Over,
+    Nip,
+    Tuck,
+    Rot,
+    Pick,
+    Roll,
+    /// Drop as many entries as the top of the stack says.
+    DropN,
+    /// Duplicate as many entries as the top of the stack says, keeping their order.
+    DupN,
+    /// Drop all but as many entries as the top of the stack says.
+    Keep,
+    Empty,
+    Depth,
+    Answer,
+    /// The result of a line, numbered from 1 in the order they were recorded.
+    PastResult(usize),
+    /// Print the recorded results.
+    Results,
+    Sum,
+    Product,
+    Min,
+    Max,
+    MinAll,
+    MaxAll,
+    /// Reverse the order of the whole stack.
+    Reverse,
+    /// Sort the whole stack, least at the bottom.
+    Sort,
+    /// Sort the whole stack, greatest at the bottom.
+    ReverseSort,
+    Random,
+    RandomInt,
+    Seed(u64),
+    /// A statistic of the whole stack.
+    Summary(Statistic),
+    /// A statistic of the top n entries.
+    SummaryOf(Statistic),
+    Round,
+    Floor,
+    Ceil,
+    Trunc,
+    Frac,
+    Sqrt,
+    Root,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Exponential,
+    Ln,
+    Log2,
+    Log10,
+    Logb,
+    Factorial,
+    Choose,
+    Permute,
+    Percent,
+    PercentOf,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Abs,
+    Negate,
+    Invert,
+    Store(String),
+    Recall(String),
+    StoreRegister(usize),
+    RecallRegister(usize),
+    AddToRegister(usize),
+    Switch(String),
+    Exchange(String),
+    Define,
+    EndDefine,
+    If,
+    Else,
+    Then,
+    /// The words between `if` and `else`, and between `else` and `then`.
+    IfElse(Vec<Token>, Vec<Token>),
+    Do,
+    Loop,
+    Index,
+    /// The words between `do` and `loop`.
+    Repeat(Vec<Token>),
+    WordSize(Option<u32>),
+    Precision(usize),
+    /// Limit how many bits results may have, or don't.
+    MaxBits(Option<usize>),
+    Radix(Base),
+    Style(Notation),
+    /// Group decimal digits in threes with a separator, or don't.
+    Group(Option<char>),
+    Angles(Angle),
+    Visible(Show),
+    Listing(Order),
+    /// Print each token and the stack after it as a line is evaluated, or don't.
+    Trace(bool),
+    /// Skip tokens that can't be read and undefined names with a warning, or don't.
+    Lenient(bool),
+    Undo,
+    Redo,
+    Help(Option<String>),
+    /// Open the stack in the REPL's full screen view once the line is done.
+    Browse,
+    /// Switch the REPL's line editor to the key bindings once the line is done.
+    SetKeymap(Keymap),
+    /// Print the bits of the top entry.
+    Bits,
+    /// Print the top entry as a continued fraction.
+    ShowContinuedFraction,
+    Approximate,
+    /// An IPv4 address, and the prefix length of its subnet.
+    Address(u32, Option<u32>),
+    Network,
+    Broadcast,
+    Hosts,
+    InNetwork,
+    /// Print the top entry as an IPv4 address.
+    ShowAddress,
+    Save(String),
+    Load(String),
+    /// Copy the entry this far below the top, or the top, to the clipboard.
+    Copy(Option<usize>),
+    Convert(Unit),
+    /// The tokens compiled from an infix expression.
+    Infix(Vec<Token>),
+}
+
+fn unexpected_trailing_chars(
+    from: &'_ str,
+    token: Token,
+    size: usize,
+) -> Result<Token, TokenError> {
+    if from.len() == size {
+        Ok(token)
+    } else {
+        Err(TokenError {
+            error: CalcError::TrailingChars,
+            span: size..(from.len()),
+        })
+    }
+}
+
+/// Parse a base 10 literal, with an optional fractional part and exponent, into an exact
+/// rational.
+///
+/// Exponents that would give the literal more than `max_bits` bits are rejected before the
+/// power of ten is computed.
+fn parse_decimal(from: &'_ str, max_bits: Option<usize>) -> Result<Rational, TokenError> {
+    let (mantissa, exponent) = match from.find(['e', 'E']) {
+        Some(e) => (&from[..e], Some(&from[e + 1..])),
+        None => (from, None),
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(dot) => (&mantissa[..dot], &mantissa[dot + 1..]),
+        None => (mantissa, ""),
+    };
+    let whole = Int::from_str_radix(whole, 10).map_err(|e| TokenError {
+        error: CalcError::InvalidInteger(e),
+        span: 0..whole.len(),
+    })?;
+    let fraction_start = mantissa.len() - fraction.len();
+    if let Some(bad) = fraction.find(|c: char| !c.is_ascii_digit()) {
+        return Err(TokenError {
+            error: CalcError::InvalidFraction,
+            span: (fraction_start + bad)..mantissa.len(),
+        });
+    }
+    let (mut num, mut den) = if fraction.is_empty() {
+        (whole, Int::one())
+    } else {
+        let scale = Int::from(10).pow(fraction.len());
+        let fraction = Int::from_str_radix(fraction, 10).map_err(|e| TokenError {
+            error: CalcError::InvalidInteger(e),
+            span: fraction_start..mantissa.len(),
+        })?;
+        (whole * &scale + fraction, scale)
+    };
+    if let Some(exponent) = exponent {
+        let (negative, digits) = match exponent.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, exponent.strip_prefix('+').unwrap_or(exponent)),
+        };
+        let power = digits.parse::<usize>().map_err(|e| TokenError {
+            error: CalcError::InvalidExponent(e),
+            span: mantissa.len()..from.len(),
+        })?;
+        // Each power of ten adds log2(10), or a little over 3.32, bits.
+        let bits = power.saturating_mul(3322) / 1000;
+        match max_bits {
+            Some(limit) if bits > limit => {
+                return Err(TokenError {
+                    error: CalcError::ResultTooLarge(limit),
+                    span: mantissa.len()..from.len(),
+                })
+            }
+            _ => (),
+        }
+        if negative {
+            den *= Int::from(10).pow(power);
+        } else {
+            num *= Int::from(10).pow(power);
+        }
+    }
+    Ok(Rational::new(num, den))
+}
+
+/// Parse a literal that starts with a digit, which may have at most `max_bits` bits.
+fn parse_number(from: &'_ str, max_bits: Option<usize>) -> Result<Token, TokenError> {
+    if date::is_date(from) {
+        return date::parse(from).map(|days| Measure(Quantity::date(days)));
+    }
+    if ipv4::is_address(from) {
+        return ipv4::parse(from).map(|(address, prefix)| Address(address, prefix));
+    }
+    let radix = match from.get(..2) {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        _ => {
+            return match from.find('#') {
+                Some(hash) => parse_radix(from, hash),
+                None => parse_literal(from, max_bits),
+            }
+        }
+    };
+    match Int::from_str_radix(&from[2..], radix) {
+        Ok(n) => Ok(Number(n.into())),
+        Err(e) => Err(TokenError {
+            error: CalcError::InvalidInteger(e),
+            span: 2..from.len(),
+        }),
+    }
+}
+
+/// Remove the `_` digit separators from the literal `from` and `parse` what's left.
+///
+/// Each separator must be between two digits, and spans of errors from `parse` are moved back
+/// to where they are in `from`.
+fn without_separators(
+    from: &'_ str,
+    parse: impl Fn(&str) -> Result<Token, TokenError>,
+) -> Result<Token, TokenError> {
+    if !from.contains('_') {
+        return parse(from);
+    }
+    // Letters are digits after a prefix, but elsewhere they may start a unit.
+    let prefix = match from.get(..2) {
+        Some("0x") | Some("0b") => Some(2),
+        _ => from.find('#').map(|hash| hash + 1),
+    };
+    let is_digit = |i: usize, b: u8| match prefix {
+        Some(start) if i >= start => b.is_ascii_alphanumeric(),
+        _ => b.is_ascii_digit(),
+    };
+    let bytes = from.as_bytes();
+    let mut stripped = String::with_capacity(from.len());
+    // The offset in `from` of each byte of `stripped`, and of its end.
+    let mut offsets = Vec::with_capacity(from.len() + 1);
+    for (i, c) in from.char_indices() {
+        if c == '_' {
+            let before = i > 0 && is_digit(i - 1, bytes[i - 1]);
+            let after = i + 1 < bytes.len() && is_digit(i + 1, bytes[i + 1]);
+            if !(before && after) {
+                return Err(TokenError {
+                    error: CalcError::MisplacedSeparator,
+                    span: i..(i + 1),
+                });
+            }
+        } else {
+            stripped.push(c);
+            offsets.extend(i..(i + c.len_utf8()));
+        }
+    }
+    offsets.push(from.len());
+    parse(&stripped).map_err(|e| {
+        let end = match e.span.end {
+            0 => 0,
+            end => offsets[end - 1] + 1,
+        };
+        TokenError {
+            span: offsets[e.span.start]..end.max(offsets[e.span.start]),
+            ..e
+        }
+    })
+}
+
+/// Suffixes that multiply a literal by a power of ten or two, such as the `k` of `4k`, as
+/// `(suffix, base, power)`.
+const MAGNITUDES: &[(&str, u32, usize)] = &[
+    ("k", 10, 3),
+    ("M", 10, 6),
+    ("G", 10, 9),
+    ("T", 10, 12),
+    ("P", 10, 15),
+    ("Ki", 2, 10),
+    ("Mi", 2, 20),
+    ("Gi", 2, 30),
+    ("Ti", 2, 40),
+    ("Pi", 2, 50),
+];
+
+/// Parse a base 10 literal, which may be followed by a unit as in `9.8m/s^2` or by a magnitude
+/// as in `16Ki`.
+fn parse_literal(from: &'_ str, max_bits: Option<usize>) -> Result<Token, TokenError> {
+    if let Some(duration) = Quantity::duration(from, max_bits) {
+        return Ok(Measure(duration));
+    }
+    // The unit starts at the first letter, unless that's the `e` of an exponent.
+    let bytes = from.as_bytes();
+    let unit_start = from.char_indices().find(|&(i, c)| {
+        let exponent = matches!(c, 'e' | 'E')
+            && bytes
+                .get(i + 1)
+                .is_some_and(|b| b.is_ascii_digit() || *b == b'+' || *b == b'-');
+        c.is_alphabetic() && !exponent
+    });
+    match unit_start {
+        Some((start, _)) => {
+            let value = parse_decimal(&from[..start], max_bits)?;
+            let suffix = &from[start..];
+            if let Some(&(_, base, power)) = MAGNITUDES.iter().find(|(name, ..)| *name == suffix) {
+                return Ok(Number(value * Rational::from(Int::from(base).pow(power))));
+            }
+            match Unit::parse(suffix) {
+                Some(unit) => Ok(Measure(Quantity::new(value, unit))),
+                None => Err(TokenError {
+                    error: CalcError::InvalidUnit,
+                    span: start..from.len(),
+                }),
+            }
+        }
+        None => parse_decimal(from, max_bits).map(Number),
+    }
+}
+
+/// Parse a continued fraction, written as `[a0;a1,a2,...]`, into the number it's equal to.
+fn parse_continued_fraction(from: &'_ str) -> Result<Token, TokenError> {
+    let terms = match from.strip_prefix('[').and_then(|f| f.strip_suffix(']')) {
+        Some(terms) => terms,
+        None => {
+            return Err(TokenError {
+                error: CalcError::InvalidContinuedFraction,
+                span: 0..from.len(),
+            })
+        }
+    };
+    let (first, rest) = match terms.find(';') {
+        Some(semicolon) => (
+            &terms[..semicolon],
+            terms[semicolon + 1..].split(',').collect(),
+        ),
+        None => (terms, Vec::new()),
+    };
+    let mut start = 1;
+    let mut parsed = Vec::new();
+    for term in core::iter::once(first).chain(rest) {
+        match term.parse::<Int>() {
+            Ok(n) if n > 0 || parsed.is_empty() => parsed.push(n),
+            _ => {
+                return Err(TokenError {
+                    error: CalcError::InvalidContinuedFraction,
+                    span: start..(start + term.len()).max(start + 1),
+                })
+            }
+        }
+        start += term.len() + 1;
+    }
+    // Evaluate from the innermost term out.
+    let mut parsed = parsed.into_iter().rev();
+    let mut num = Rational::new(parsed.next().unwrap(), Int::one());
+    for term in parsed {
+        let (n, d) = num.into_parts();
+        num = Rational::new(term, Int::one()) + Rational::new(d, n);
+    }
+    Ok(Number(num))
+}
+
+/// Parse an integer written as `radix#digits`, such as `36#zz`, where `hash` is the index of
+/// the `#`.
+fn parse_radix(from: &'_ str, hash: usize) -> Result<Token, TokenError> {
+    let (radix, digits) = (&from[..hash], &from[hash + 1..]);
+    let radix = match radix.parse::<u32>() {
+        Ok(radix) if (2..=36).contains(&radix) => radix,
+        _ => {
+            return Err(TokenError {
+                error: CalcError::InvalidRadix,
+                span: 0..hash,
+            })
+        }
+    };
+    if digits.is_empty() {
+        return Err(TokenError {
+            error: CalcError::MissingDigits,
+            span: hash..from.len(),
+        });
+    }
+    if let Some((bad, c)) = digits.char_indices().find(|(_, c)| !c.is_digit(radix)) {
+        let start = hash + 1 + bad;
+        return Err(TokenError {
+            error: CalcError::InvalidDigit(radix),
+            span: start..(start + c.len_utf8()),
+        });
+    }
+    Int::from_str_radix(digits, radix as u8)
+        .map(|n| Number(n.into()))
+        .map_err(|e| TokenError {
+            error: CalcError::InvalidInteger(e),
+            span: (hash + 1)..from.len(),
+        })
+}
+
+/// Check that `from` is a valid variable name: a letter followed by letters, digits or
+/// underscores.
+fn parse_name(from: &'_ str) -> Result<&'_ str, TokenError> {
+    match from.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')) {
+        Some(bad) => Err(TokenError {
+            error: CalcError::InvalidName,
+            span: bad..from.len(),
+        }),
+        None => Ok(from),
+    }
+}
+
+/// Writes the token as input that lexes to an equivalent token, or to tokens with the same effect.
+impl Display for Token {
+    fn fmt(&self, f: &mut FmtFormatter<'_>) -> fmt::Result {
+        match self {
+            // There are no fraction or negative literals, so those are built with operators.
+            Number(num) => {
+                let (num, den) = num.clone().into_parts();
+                write!(f, "{}", num.clone().abs())?;
+                if !den.is_one() {
+                    write!(f, " {} /", den)?;
+                }
+                if num < 0 {
+                    f.write_str(" neg")?;
+                }
+                Ok(())
+            }
+            Measure(quantity) if quantity.date => f.write_str(&date::format(quantity.value())),
+            Measure(quantity) => match quantity.unit() {
+                Some(unit) => {
+                    let (num, den) = quantity.value().clone().into_parts();
+                    if den.is_one() && num >= 0 {
+                        write!(f, "{}{}", num, unit.name())
+                    } else {
+                        // Only whole numbers can be written in front of a unit, so scale one
+                        // of the unit instead.
+                        let value = Number(quantity.value().clone());
+                        write!(f, "{} 1{} *", value, unit.name())
+                    }
+                }
+                None => Number(quantity.value().clone()).fmt(f),
+            },
+            Address(address, Some(prefix)) => write!(f, "{}/{}", ipv4::format(*address), prefix),
+            Address(address, None) => f.write_str(&ipv4::format(*address)),
+            Store(name) => write!(f, "={}", name),
+            Recall(name) => f.write_str(name),
+            StoreRegister(register) => write!(f, "sto {}", register),
+            RecallRegister(register) => write!(f, "rcl {}", register),
+            AddToRegister(register) => write!(f, "sto+ {}", register),
+            Switch(name) => write!(f, "stack {}", name),
+            Exchange(name) => write!(f, "xchg {}", name),
+            WordSize(Some(bits)) => write!(f, "word {}", bits),
+            WordSize(None) => f.write_str("word unlimited"),
+            Precision(digits) => write!(f, "prec {}", digits),
+            MaxBits(Some(bits)) => write!(f, "maxbits {}", bits),
+            MaxBits(None) => f.write_str("maxbits unlimited"),
+            Seed(seed) => write!(f, "seed {}", seed),
+            Visible(Show::Top) => f.write_str("show top"),
+            Visible(Show::All) => f.write_str("show all"),
+            Visible(Show::Changed) => f.write_str("show changed"),
+            Listing(Order::TopFirst) => f.write_str("order top"),
+            Listing(Order::BottomFirst) => f.write_str("order bottom"),
+            PastResult(index) => write!(f, "${}", index),
+            Trace(true) => f.write_str("trace on"),
+            Trace(false) => f.write_str("trace off"),
+            Lenient(true) => f.write_str("lenient on"),
+            Lenient(false) => f.write_str("lenient off"),
+            SetKeymap(Keymap::Vi) => f.write_str("set keymap vi"),
+            SetKeymap(Keymap::Emacs) => f.write_str("set keymap emacs"),
+            Group(Some(separator)) => write!(f, "group {}", separator),
+            Group(None) => f.write_str("group off"),
+            Help(Some(topic)) => write!(f, "help {}", topic),
+            Save(path) => write!(f, "save {}", path),
+            Load(path) => write!(f, "load {}", path),
+            Copy(Some(index)) => write!(f, "copy {}", index),
+            Convert(unit) => write!(f, "to {}", unit.name()),
+            IfElse(then, otherwise) => {
+                f.write_str("if")?;
+                for token in then {
+                    write!(f, " {}", token)?;
+                }
+                if !otherwise.is_empty() {
+                    f.write_str(" else")?;
+                    for token in otherwise {
+                        write!(f, " {}", token)?;
+                    }
+                }
+                f.write_str(" then")
+            }
+            Infix(tokens) => {
+                let tokens: Vec<String> = tokens.iter().map(Token::to_string).collect();
+                f.write_str(&tokens.join(" "))
+            }
+            Repeat(body) => {
+                f.write_str("do")?;
+                for token in body {
+                    write!(f, " {}", token)?;
+                }
+                f.write_str(" loop")
+            }
+            // Everything else is spelled by the registry.
+            token => f.write_str(operators::name(token).unwrap_or("")),
+        }
+    }
+}
+
+impl FromStr for Token {
+    type Err = TokenError;
+    /// Read a single token, with literals limited to the default number of bits.
+    fn from_str(from: &'_ str) -> Result<Self, Self::Err> {
+        Token::read(from, Some(DEFAULT_MAX_BITS))
+    }
+}
+
+impl Token {
+    /// Read the single token `from`, rejecting literals with more than `max_bits` bits.
+    fn read(from: &'_ str, max_bits: Option<usize>) -> Result<Token, TokenError> {
+        let mut chars = from.chars();
+        match chars.next().ok_or(TokenError {
+            error: CalcError::EmptyToken,
+            span: 0..0,
+        })? {
+            c if c.is_ascii_digit() => {
+                without_separators(from, |from| parse_number(from, max_bits))
+            }
+            '[' => parse_continued_fraction(from),
+            '$' => parse_past_result(from),
+            c if c.is_ascii_alphabetic() => match operators::find(from).map(|op| &op.syntax) {
+                Some(Syntax::Token(token)) => Ok(token.clone()),
+                Some(Syntax::Command(_)) => Err(TokenError {
+                    error: CalcError::MissingArgument,
+                    span: 0..from.len(),
+                }),
+                None => parse_name(from).map(|name| Recall(name.to_string())),
+            },
+            '=' if from.len() == 1 => Err(TokenError {
+                error: CalcError::MissingVariableName,
+                span: 0..1,
+            }),
+            '=' => match Token::read(&from[1..], max_bits) {
+                Ok(Recall(name)) => Ok(Store(name)),
+                Ok(_) => Err(TokenError {
+                    error: CalcError::ExpectedVariableName,
+                    span: 1..from.len(),
+                }),
+                Err(e) => Err(TokenError {
+                    span: (e.span.start + 1)..(e.span.end + 1),
+                    ..e
+                }),
+            },
+            _ => match operators::find_symbol(from).map(|(op, size)| (&op.syntax, size)) {
+                Some((Syntax::Token(token), size)) => {
+                    unexpected_trailing_chars(from, token.clone(), size)
+                }
+                _ => Err(TokenError {
+                    error: CalcError::UnexpectedToken,
+                    span: 0..from.len(),
+                }),
+            },
+        }
+    }
+}
+
+fn subslice_offset(slice: &str, sub: &str) -> Option<usize> {
+    let self_begin = slice.as_ptr() as usize;
+    let inner = sub.as_ptr() as usize;
+    if inner < self_begin || inner > self_begin.wrapping_add(slice.len()) {
+        None
+    } else {
+        Some(inner.wrapping_sub(self_begin))
+    }
+}
+
+/// Parse the argument of the `word` command: 8, 16, 32 or 64 bits, or `unlimited`.
+fn parse_word_size(from: &'_ str) -> Result<Token, TokenError> {
+    match from {
+        "8" => Ok(WordSize(Some(8))),
+        "16" => Ok(WordSize(Some(16))),
+        "32" => Ok(WordSize(Some(32))),
+        "64" => Ok(WordSize(Some(64))),
+        "unlimited" => Ok(WordSize(None)),
+        _ => Err(TokenError {
+            error: CalcError::InvalidWordSize,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the argument of the `maxbits` command: a number of bits, or `unlimited`.
+fn parse_max_bits(from: &'_ str) -> Result<Token, TokenError> {
+    match from {
+        "unlimited" => Ok(MaxBits(None)),
+        _ => match from.parse::<usize>() {
+            Ok(bits) if bits > 0 => Ok(MaxBits(Some(bits))),
+            _ => Err(TokenError {
+                error: CalcError::InvalidBitLimit,
+                span: 0..from.len(),
+            }),
+        },
+    }
+}
+
+/// Parse the argument of the `prec` command: a number of decimal digits.
+fn parse_precision(from: &'_ str) -> Result<Token, TokenError> {
+    from.parse::<usize>()
+        .map(Precision)
+        .map_err(|_| TokenError {
+            error: CalcError::InvalidPrecision,
+            span: 0..from.len(),
+        })
+}
+
+/// Parse the argument of the `seed` command: the state of the random number generator.
+fn parse_seed(from: &'_ str) -> Result<Token, TokenError> {
+    from.parse::<u64>().map(Seed).map_err(|_| TokenError {
+        error: CalcError::InvalidSeed,
+        span: 0..from.len(),
+    })
+}
+
+/// Parse a `$n` reference to the result of an earlier line.
+fn parse_past_result(from: &'_ str) -> Result<Token, TokenError> {
+    match from[1..].parse::<usize>() {
+        Ok(index) if index > 0 => Ok(PastResult(index)),
+        _ => Err(TokenError {
+            error: CalcError::InvalidResult,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the number of a register, the argument of `sto`, `rcl` and `sto+`.
+fn parse_register(from: &'_ str) -> Result<usize, TokenError> {
+    match from.parse::<usize>() {
+        Ok(register) if register < REGISTERS => Ok(register),
+        _ => Err(TokenError {
+            error: CalcError::InvalidRegister,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+fn parse_store_register(from: &'_ str) -> Result<Token, TokenError> {
+    parse_register(from).map(StoreRegister)
+}
+
+fn parse_recall_register(from: &'_ str) -> Result<Token, TokenError> {
+    parse_register(from).map(RecallRegister)
+}
+
+fn parse_add_to_register(from: &'_ str) -> Result<Token, TokenError> {
+    parse_register(from).map(AddToRegister)
+}
+
+/// Parse the argument of the `stack` command: the stack to switch to.
+fn parse_switch(from: &'_ str) -> Result<Token, TokenError> {
+    parse_name(from).map(|name| Switch(name.to_string()))
+}
+
+/// Parse the argument of the `xchg` command: the stack to exchange with.
+fn parse_exchange(from: &'_ str) -> Result<Token, TokenError> {
+    parse_name(from).map(|name| Exchange(name.to_string()))
+}
+
+/// Parse the argument of the `show` command: which stack entries to print.
+fn parse_show(from: &'_ str) -> Result<Token, TokenError> {
+    match from {
+        "top" => Ok(Visible(Show::Top)),
+        "all" => Ok(Visible(Show::All)),
+        "changed" => Ok(Visible(Show::Changed)),
+        _ => Err(TokenError {
+            error: CalcError::InvalidShow,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the argument of the `group` command: the separator between groups of digits, or `off`.
+fn parse_group(from: &'_ str) -> Result<Token, TokenError> {
+    let mut chars = from.chars();
+    match (chars.next(), chars.next()) {
+        _ if from == "off" => Ok(Group(None)),
+        (Some(separator), None) if separator.is_ascii_punctuation() => Ok(Group(Some(separator))),
+        _ => Err(TokenError {
+            error: CalcError::InvalidSeparator,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the argument of the `order` command: which end of the stack to print first.
+fn parse_order(from: &'_ str) -> Result<Token, TokenError> {
+    match from {
+        "top" => Ok(Listing(Order::TopFirst)),
+        "bottom" => Ok(Listing(Order::BottomFirst)),
+        _ => Err(TokenError {
+            error: CalcError::InvalidOrder,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the `on` or `off` argument of a command that turns a mode on or off.
+fn parse_toggle(from: &'_ str) -> Result<bool, TokenError> {
+    match from {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(TokenError {
+            error: CalcError::InvalidToggle,
+            span: 0..from.len(),
+        }),
+    }
+}
+
+/// Parse the argument of the `trace` command: whether to print each step of evaluation.
+fn parse_trace(from: &'_ str) -> Result<Token, TokenError> {
+    parse_toggle(from).map(Trace)
+}
+
+/// Parse the argument of the `lenient` command: whether to skip tokens that can't be used.
+fn parse_lenient(from: &'_ str) -> Result<Token, TokenError> {
+    parse_toggle(from).map(Lenient)
+}
+
+/// Parse the arguments of the `set` command: the name of a setting and its value.
+fn parse_set(from: &'_ str) -> Result<Token, TokenError> {
+    let mut words = from.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    if name != "keymap" {
+        return Err(TokenError {
+            error: CalcError::UnknownSetting,
+            span: 0..name.len(),
+        });
+    }
+    match words.next() {
+        Some("vi") => Ok(SetKeymap(Keymap::Vi)),
+        Some("emacs") => Ok(SetKeymap(Keymap::Emacs)),
+        Some(value) => {
+            let start = subslice_offset(from, value).unwrap();
+            Err(TokenError {
+                error: CalcError::InvalidKeymap,
+                span: start..start + value.len(),
+            })
+        }
+        None => Err(TokenError {
+            error: CalcError::MissingArgument,
+            span: 0..name.len(),
+        }),
+    }
+}
+
+/// Parse the argument of the `save` command: the file to write the session to.
+fn parse_save(from: &'_ str) -> Result<Token, TokenError> {
+    Ok(Save(from.to_string()))
+}
+
+/// Parse the argument of the `load` command: the file to read a session from.
+fn parse_load(from: &'_ str) -> Result<Token, TokenError> {
+    Ok(Load(from.to_string()))
+}
+
+/// Parse the argument of the `to` command: the unit to convert to.
+fn parse_unit(from: &'_ str) -> Result<Token, TokenError> {
+    Unit::parse(from).map(Convert).ok_or(TokenError {
+        error: CalcError::InvalidUnit,
+        span: 0..from.len(),
+    })
+}
+
+/// Parses the argument of a command into a token.
+type ArgumentParser = fn(&'_ str) -> Result<Token, TokenError>;
+
+impl Token {
+    /// The names of every operator and command that is spelled as a word.
+    pub fn words() -> impl Iterator<Item = &'static str> {
+        OPERATORS
+            .iter()
+            .flat_map(|op| op.names.iter().copied())
+            .filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic()))
+    }
+
+    /// Whether `line` carries on to the next line of input, because it ends with `\` or has a
+    /// `:` definition without its `;`. Literals with more than `max_bits` bits aren't computed.
+    pub fn continues(line: &str, max_bits: Option<usize>) -> bool {
+        if line.trim_end().ends_with('\\') {
+            return true;
+        }
+        let mut defining = false;
+        for token in Token::lex(line, max_bits) {
+            match token {
+                Ok((Define, _)) => defining = true,
+                Ok((EndDefine, _)) => defining = false,
+                _ => (),
+            }
+        }
+        defining
+    }
+
+    /// Split `from` into tokens, each paired with the span of the input it was read from.
+    ///
+    /// Literals that would have more than `max_bits` bits are errors, so that lexing a line,
+    /// as the REPL does on every key press, can't take long.
+    pub fn lex(
+        from: &'_ str,
+        max_bits: Option<usize>,
+    ) -> impl Iterator<Item = Result<(Token, Range<usize>), <Token as FromStr>::Err>> + '_ {
+        let mut words = from.split_whitespace();
+        core::iter::from_fn(move || {
+            let mut s = words.next()?;
+            // Comments are skipped entirely: `(` starts a comment that ends with the next word
+            // ending in `)`, and `#` starts one that runs to the end of the line.
+            while s.starts_with('(') {
+                let start = subslice_offset(from, s).unwrap();
+                while !s.ends_with(')') {
+                    s = match words.next() {
+                        Some(s) => s,
+                        None => {
+                            return Some(Err(TokenError {
+                                error: CalcError::UnterminatedComment,
+                                span: start..from.trim_end().len(),
+                            }))
+                        }
+                    };
+                }
+                s = words.next()?;
+            }
+            if s.starts_with('#') {
+                return None;
+            }
+            // Note: This is a safe unwrap, as the subslice_offset function only returns
+            // None when s is not a subslice of from. This can't happen.
+            let offset = subslice_offset(from, s).unwrap();
+            if s.starts_with("=(") {
+                return Some(lex_infix(from, offset, &mut words, max_bits));
+            }
+            // Errors in a command's argument are reported relative to the argument.
+            let (token, error_offset, end) = match operators::find(s).map(|op| &op.syntax) {
+                Some(Syntax::Command(parse)) => match words.next() {
+                    Some(argument) => {
+                        let argument_offset = subslice_offset(from, argument).unwrap();
+                        let mut end = argument_offset + argument.len();
+                        // `set` takes two words, the name of a setting and its value.
+                        if s == "set" {
+                            if let Some(value) = words.next() {
+                                end = subslice_offset(from, value).unwrap() + value.len();
+                            }
+                        }
+                        (parse(&from[argument_offset..end]), argument_offset, end)
+                    }
+                    None => (
+                        Err(TokenError {
+                            error: CalcError::MissingArgument,
+                            span: 0..s.len(),
+                        }),
+                        offset,
+                        offset + s.len(),
+                    ),
+                },
+                // `copy` takes an optional argument, the index of the entry to copy.
+                Some(Syntax::Token(Copy(None))) => match words.clone().next() {
+                    Some(index) if index.parse::<usize>().is_ok() => {
+                        words.next();
+                        let end = subslice_offset(from, index).unwrap() + index.len();
+                        (Ok(Copy(index.parse().ok())), offset, end)
+                    }
+                    _ => (Ok(Copy(None)), offset, offset + s.len()),
+                },
+                // `help` takes an optional argument, which must name an operator.
+                Some(Syntax::Token(Help(None))) => match words.clone().next() {
+                    Some(topic) if operators::find(topic).is_some() => {
+                        words.next();
+                        let end = subslice_offset(from, topic).unwrap() + topic.len();
+                        (Ok(Help(Some(topic.to_string()))), offset, end)
+                    }
+                    _ => (Ok(Help(None)), offset, offset + s.len()),
+                },
+                _ => (Token::read(s, max_bits), offset, offset + s.len()),
+            };
+            Some(
+                token
+                    .map(|token| (token, offset..end))
+                    .map_err(|e| TokenError {
+                        span: (e.span.start + error_offset)..(e.span.end + error_offset),
+                        ..e
+                    }),
+            )
+        })
+    }
+}
+
+/// Lex the infix expression that starts with the `=(` at `offset` in `from`, moving `words` past
+/// the `)` that ends it.
+fn lex_infix(
+    from: &'_ str,
+    offset: usize,
+    words: &mut core::str::SplitWhitespace<'_>,
+    max_bits: Option<usize>,
+) -> Result<(Token, Range<usize>), TokenError> {
+    let start = offset + 2;
+    let mut depth = 1;
+    let close = from[start..]
+        .char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => (),
+            }
+            depth == 0
+        })
+        .map(|(i, _)| start + i);
+    let end = close.map_or(from.len(), |close| close + 1);
+    let mut words_end = end;
+    while let Some(word) = words.clone().next() {
+        let word_offset = subslice_offset(from, word).unwrap();
+        if word_offset >= end {
+            break;
+        }
+        words_end = word_offset + word.len();
+        words.next();
+    }
+    let close = close.ok_or(TokenError {
+        error: CalcError::UnbalancedParens,
+        span: offset..from.trim_end().len(),
+    })?;
+    if words_end > end {
+        return Err(TokenError {
+            error: CalcError::TrailingChars,
+            span: end..words_end,
+        });
+    }
+    let tokens = infix::compile(&from[start..close], max_bits).map_err(|e| TokenError {
+        span: (e.span.start + start)..(e.span.end + start),
+        ..e
+    })?;
+    Ok((Infix(tokens), offset..end))
+}
+
+/// `base` to the power `exp`, by repeated squaring that stops if the line is interrupted.
+pub(crate) fn int_pow(base: &Int, exp: usize, interrupt: &Interrupt) -> Result<Int, CalcError> {
+    let mut result = Int::one();
+    for i in (0..usize::BITS - exp.leading_zeros()).rev() {
+        interrupt.check()?;
+        result = result.square();
+        if exp >> i & 1 == 1 {
+            result *= base;
+        }
+    }
+    Ok(result)
+}
+
+/// Raise `base` to the integer power `exp`.
+///
+/// Negative exponents produce the reciprocal of the positive power.
+fn pow(base: Rational, exp: Rational, interrupt: &Interrupt) -> Result<Rational, CalcError> {
+    let (exp, exp_den) = exp.into_parts();
+    if !exp_den.is_one() {
+        return Err(CalcError::NonIntegerExponent);
+    }
+    let negative = exp < 0;
+    let exp = exp.abs();
+    if exp > usize::MAX {
+        return Err(CalcError::ExponentTooLarge);
+    }
+    let exp = usize::from(&exp);
+    let (num, den) = base.into_parts();
+    if negative && num.is_zero() {
+        return Err(CalcError::DivisionByZero);
+    }
+    let num = int_pow(&num, exp, interrupt)?;
+    let den = int_pow(&den, exp, interrupt)?;
+    if negative {
+        Ok(Rational::new(den, num))
+    } else {
+        Ok(Rational::new(num, den))
+    }
+}
+
+/// Round `num` towards negative infinity.
+fn floor(num: Rational) -> Int {
+    let (num, den) = num.into_parts();
+    let (quotient, remainder) = num.divmod(&den);
+    if remainder < 0 {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+/// The terms of the continued fraction that is equal to `num`.
+fn continued_fraction(mut num: Rational) -> Vec<Int> {
+    let mut terms = Vec::new();
+    loop {
+        let term = floor(num.clone());
+        let fraction = num - Rational::new(term.clone(), Int::one());
+        terms.push(term);
+        if fraction == Rational::zero() {
+            return terms;
+        }
+        let (n, d) = fraction.into_parts();
+        num = Rational::new(d, n);
+    }
+}
+
+/// The closest rational to `num` with a denominator of at most `max`, which is at least 1.
+///
+/// Every best approximation is a convergent of the continued fraction of `num`, or a
+/// semiconvergent between the last convergent that fits and the first that doesn't.
+fn best_approximation(num: Rational, max: &Int) -> Rational {
+    let fraction = |n: &Int, d: &Int| Rational::new(n.clone(), d.clone());
+    let (mut p0, mut q0, mut p1, mut q1) = (Int::zero(), Int::one(), Int::one(), Int::zero());
+    for term in continued_fraction(num.clone()) {
+        let q = &term * &q1 + &q0;
+        if q > *max {
+            let k = (max - &q0) / &q1;
+            let semiconvergent = fraction(&(&k * &p1 + &p0), &(&k * &q1 + &q0));
+            let convergent = fraction(&p1, &q1);
+            let error = |approximation: &Rational| (approximation.clone() - num.clone()).abs();
+            // Ties go to the convergent, which has the smaller denominator.
+            return if error(&semiconvergent) < error(&convergent) {
+                semiconvergent
+            } else {
+                convergent
+            };
+        }
+        let p = &term * &p1 + &p0;
+        (p0, q0, p1, q1) = (p1, q1, p, q);
+    }
+    num
+}
+
+/// Round `num` towards positive infinity.
+fn ceil(num: Rational) -> Int {
+    -floor(-num)
+}
+
+/// Round `num` towards zero.
+fn trunc(num: Rational) -> Int {
+    let (num, den) = num.into_parts();
+    num / den
+}
+
+/// The remainder of floored division; the result takes the sign of `rhs`.
+fn modulo(lhs: Rational, rhs: Rational) -> Rational {
+    let quotient = Rational::from(floor(&lhs / &rhs));
+    lhs - rhs * quotient
+}
+
+/// Convert `num` to a count, if it is a non-negative integer that fits in a `usize`.
+fn to_count(num: &Rational) -> Option<usize> {
+    let (num, den) = num.clone().into_parts();
+    if den.is_one() && num >= 0 && num <= usize::MAX {
+        Some(usize::from(&num))
+    } else {
+        None
+    }
+}
+
+/// Convert `num` into a modulus, which must be a positive integer.
+fn to_modulus(num: Rational) -> Result<Int, CalcError> {
+    match num.into_parts() {
+        (num, den) if den.is_one() && num > 0 => Ok(num),
+        _ => Err(CalcError::InvalidModulus),
+    }
+}
+
+/// The inverse of `num` modulo `modulus`, found with the extended Euclidean algorithm, if they
+/// have no common factor.
+fn inverse_mod(num: Int, modulus: &Int) -> Option<Int> {
+    // Each remainder r is x * num, modulo the modulus.
+    let (mut r0, mut r1) = (modulus.clone(), (num % modulus + modulus) % modulus);
+    let (mut x0, mut x1) = (Int::zero(), Int::one());
+    while r1 != 0 {
+        let (quotient, remainder) = r0.divmod(&r1);
+        let x = x0 - &quotient * &x1;
+        (r0, r1, x0, x1) = (r1, remainder, x1, x);
+    }
+    if r0.is_one() {
+        Some((x0 % modulus + modulus) % modulus)
+    } else {
+        None
+    }
+}
+
+/// Convert `num` to an integer, rejecting anything with a fractional part.
+fn to_integer(num: Rational) -> Result<Int, CalcError> {
+    let (num, den) = num.into_parts();
+    if den.is_one() {
+        Ok(num)
+    } else {
+        Err(CalcError::NotAnInteger)
+    }
+}
+
+/// The least common multiple of `lhs` and `rhs`, which is zero if either is zero.
+fn lcm(lhs: &Int, rhs: &Int) -> Int {
+    if lhs.is_zero() || rhs.is_zero() {
+        Int::zero()
+    } else {
+        lhs.lcm(rhs)
+    }
+}
+
+/// The largest operand `fact` accepts; anything bigger takes too long to be useful.
+const MAX_FACTORIAL: usize = 10_000;
+
+/// The product of the integers in `lo..hi`.
+///
+/// Splitting the range in half keeps the operands of each multiplication similar in
+/// size, which is much faster than multiplying in one at a time.
+fn product(lo: usize, hi: usize) -> Int {
+    match hi.saturating_sub(lo) {
+        0 => Int::one(),
+        1 => Int::from(lo),
+        len => {
+            let mid = lo + len / 2;
+            product(lo, mid) * product(mid, hi)
+        }
+    }
+}
+
+/// Compute `num!`, rejecting anything that isn't a small enough non-negative integer.
+fn factorial(num: &Rational, settings: &Settings) -> Result<Int, CalcError> {
+    match to_count(num) {
+        Some(n) if n <= MAX_FACTORIAL => {
+            check_terms(&Int::from(n), n, settings)?;
+            Ok(product(1, n + 1))
+        }
+        Some(_) => Err(CalcError::FactorialTooLarge),
+        None => Err(CalcError::NotANaturalNumber),
+    }
+}
+
+/// Convert the operands of `choose` and `perm` to non-negative integers.
+fn combinatoric_operands(n: Rational, k: Rational) -> Result<(Int, Int), CalcError> {
+    match (to_integer(n), to_integer(k)) {
+        (Ok(n), Ok(k)) if n >= 0 && k >= 0 => Ok((n, k)),
+        _ => Err(CalcError::NotANaturalNumber),
+    }
+}
+
+/// Convert the number of terms in a product to a `usize`, held to the same limit as `fact`.
+fn term_count(k: &Int) -> Result<usize, CalcError> {
+    if *k <= MAX_FACTORIAL {
+        Ok(usize::from(k))
+    } else {
+        Err(CalcError::TooManyItems)
+    }
+}
+
+/// Check that a product of `k` terms, none bigger than `n`, fits within the limit.
+fn check_terms(n: &Int, k: usize, settings: &Settings) -> Result<(), CalcError> {
+    settings.check_bits((n.bit_length() as usize).saturating_mul(k))
+}
+
+/// The number of ways to choose `k` items from `n` without regard to order.
+fn choose(
+    n: Rational,
+    k: Rational,
+    settings: &Settings,
+    interrupt: &Interrupt,
+) -> Result<Int, CalcError> {
+    let (n, k) = combinatoric_operands(n, k)?;
+    if k > n {
+        return Ok(Int::zero());
+    }
+    // Choosing k items is the same as choosing the n - k to leave behind, so use whichever
+    // needs fewer terms.
+    let rest = &n - &k;
+    let k = term_count(core::cmp::min(&k, &rest))?;
+    check_terms(&n, k, settings)?;
+    // Each partial result is itself a binomial coefficient, so the division is always exact.
+    let start = n - Int::from(k);
+    (1..=k).try_fold(Int::one(), |acc, i| {
+        interrupt.check()?;
+        Ok(acc * (&start + Int::from(i)) / Int::from(i))
+    })
+}
+
+/// The number of ways to arrange `k` items chosen from `n`.
+fn permute(
+    n: Rational,
+    k: Rational,
+    settings: &Settings,
+    interrupt: &Interrupt,
+) -> Result<Int, CalcError> {
+    let (n, k) = combinatoric_operands(n, k)?;
+    if k > n {
+        return Ok(Int::zero());
+    }
+    let k = term_count(&k)?;
+    check_terms(&n, k, settings)?;
+    (0..k).try_fold(Int::one(), |acc, i| {
+        interrupt.check()?;
+        Ok(acc * (&n - Int::from(i)))
+    })
+}
+
+/// The number of bits in the larger of the numerator and denominator of `num`.
+fn bit_size(num: &Rational) -> usize {
+    let (num, den) = num.clone().into_parts();
+    num.bit_length().max(den.bit_length()) as usize
+}
+
+/// About the number of bits in `quantity` in SI base units, the product of its value and the
+/// scale of its unit. It's the same as [`bit_size`] for quantities in base units.
+fn quantity_bits(quantity: &Quantity) -> usize {
+    let scale = quantity.unit().map_or(1, |unit| bit_size(unit.scale()));
+    bit_size(quantity.value()) + scale - 1
+}
+
+/// Convert `num` to a shift count, rejecting anything that is not a non-negative integer.
+fn shift_count(num: &Rational) -> Result<usize, CalcError> {
+    to_count(num).ok_or(CalcError::InvalidShift)
+}
+
+/// Shift `num` right, rounding towards negative infinity as a two's complement shift would.
+fn shift_right(num: Int, count: usize) -> Int {
+    if num < 0 {
+        -((-num - 1_i32) >> count) - 1_i32
+    } else {
+        num >> count
+    }
+}
+
+/// The key bindings of the REPL's line editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keymap {
+    Vi,
+    Emacs,
+}
+
+/// Modes that change how the calculator evaluates and displays numbers.
+#[derive(Clone)]
+pub struct Settings {
+    /// The number of bits that the bitwise operators work with, or `None` for unlimited.
+    word_size: Option<u32>,
+    /// The number of decimal digits that approximated results are accurate to.
+    precision: usize,
+    /// The unit of angles for trigonometric functions.
+    angle: Angle,
+    /// The most bits that a result may have, or `None` for unlimited.
+    max_bits: Option<usize>,
+    /// Whether tokens that can't be read and undefined names are skipped rather than errors.
+    lenient: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            word_size: None,
+            precision: approx::DEFAULT_PRECISION,
+            angle: Angle::default(),
+            max_bits: Some(DEFAULT_MAX_BITS),
+            lenient: false,
+        }
+    }
+}
+
+impl Settings {
+    /// The number of bits that the bitwise operators work with, or `None` for unlimited.
+    pub fn word_size(&self) -> Option<u32> {
+        self.word_size
+    }
+
+    /// The number of decimal digits that approximated results are accurate to.
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    /// The unit of angles for trigonometric functions.
+    pub fn angle(&self) -> Angle {
+        self.angle
+    }
+
+    /// The most bits that a result may have, or `None` for unlimited.
+    pub fn max_bits(&self) -> Option<usize> {
+        self.max_bits
+    }
+
+    /// Whether tokens that can't be read and undefined names are skipped rather than errors.
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Check that a result of `bits` bits is within the limit, before it's computed.
+    fn check_bits(&self, bits: usize) -> Result<(), CalcError> {
+        match self.max_bits {
+            Some(limit) if bits > limit => Err(CalcError::ResultTooLarge(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wrap `num` into the unsigned range of the word size.
+    pub fn wrap_unsigned(&self, num: Int) -> Int {
+        match self.word_size {
+            Some(bits) => {
+                let modulus = Int::one() << bits as usize;
+                let remainder = num % &modulus;
+                if remainder < 0 {
+                    remainder + modulus
+                } else {
+                    remainder
+                }
+            }
+            None => num,
+        }
+    }
+
+    /// Wrap `num` into the two's complement signed range of the word size.
+    fn wrap_signed(&self, num: Int) -> Int {
+        match self.word_size {
+            Some(bits) => {
+                let unsigned = self.wrap_unsigned(num);
+                if unsigned.bit(bits - 1) {
+                    unsigned - (Int::one() << bits as usize)
+                } else {
+                    unsigned
+                }
+            }
+            None => num,
+        }
+    }
+
+    /// Round `num` to an integer and wrap it into the word size.
+    fn to_word(&self, num: Rational) -> Int {
+        self.wrap_signed(num.round())
+    }
+
+    /// Round `num` to an integer and wrap it into the unsigned range of the word size.
+    ///
+    /// Without a word size, negative numbers have infinitely many ones and are rejected.
+    fn to_unsigned(&self, num: Rational) -> Result<Int, CalcError> {
+        let num = self.wrap_unsigned(num.round());
+        if num < 0 {
+            Err(CalcError::NeedsWordSize)
+        } else {
+            Ok(num)
+        }
+    }
+
+    /// Read `num` as the index of a bit, which must be within the word size.
+    fn bit_index(&self, num: &Rational) -> Result<u32, CalcError> {
+        to_count(num)
+            .and_then(|index| core::convert::TryFrom::try_from(index).ok())
+            .filter(|&index| self.word_size.is_none_or(|bits| index < bits))
+            .ok_or(CalcError::InvalidBit)
+            .and_then(|index| self.check_bits(index as usize + 1).map(|_| index))
+    }
+
+    /// The high and low bit indices of a range of bits, which includes both.
+    fn bit_range(&self, high: &Rational, low: &Rational) -> Result<(u32, u32), CalcError> {
+        let (high, low) = (self.bit_index(high)?, self.bit_index(low)?);
+        if high < low {
+            return Err(CalcError::InvalidBitRange);
+        }
+        Ok((high, low))
+    }
+
+    /// Wrap the result of a bitwise operation back into a stack value.
+    fn wrap_result(&self, num: Int) -> Rational {
+        Rational::new(self.wrap_signed(num), 1.into())
+    }
+}
+
+/// Group the tokens of each `if ... else ... then` into an [`IfElse`] token, and of each
+/// `do ... loop` into a [`Repeat`] token.
+fn nest(tokens: Vec<(Token, Range<usize>)>) -> Result<Vec<(Token, Range<usize>)>, TokenError> {
+    let mut tokens = tokens.into_iter();
+    match nest_block(&mut tokens, 0)? {
+        (nested, None) => Ok(nested),
+        (_, Some(end)) => Err(unexpected_end(end)),
+    }
+}
+
+/// The error for an `else`, `then` or `loop` that doesn't end the block it's in.
+fn unexpected_end((token, span): (Token, Range<usize>)) -> TokenError {
+    let error = match token {
+        Loop => CalcError::UnexpectedLoop,
+        _ => CalcError::UnexpectedBranch,
+    };
+    TokenError { error, span }
+}
+
+/// Take tokens up to the first `else`, `then` or `loop` that isn't part of a nested block,
+/// returning that token as well. `depth` counts the blocks that the tokens are nested within.
+#[allow(clippy::type_complexity)]
+fn nest_block(
+    tokens: &mut impl Iterator<Item = (Token, Range<usize>)>,
+    depth: usize,
+) -> Result<(Vec<(Token, Range<usize>)>, Option<(Token, Range<usize>)>), TokenError> {
+    let mut nested = Vec::new();
+    while let Some((token, span)) = tokens.next() {
+        if matches!(token, If | Do) && depth >= MAX_BLOCK_DEPTH {
+            return Err(TokenError {
+                error: CalcError::NestedTooDeep,
+                span,
+            });
+        }
+        match token {
+            If => {
+                let (then, end) = nest_block(tokens, depth + 1)?;
+                let (otherwise, end) = match end {
+                    Some((Else, _)) => nest_block(tokens, depth + 1)?,
+                    end => (Vec::new(), end),
+                };
+                let end = match end {
+                    Some((Then, end)) => end.end,
+                    Some(end) => return Err(unexpected_end(end)),
+                    None => {
+                        return Err(TokenError {
+                            error: CalcError::UnterminatedConditional,
+                            span,
+                        })
+                    }
+                };
+                nested.push((
+                    IfElse(unspanned(then), unspanned(otherwise)),
+                    span.start..end,
+                ));
+            }
+            Do => {
+                let (body, end) = nest_block(tokens, depth + 1)?;
+                let end = match end {
+                    Some((Loop, end)) => end.end,
+                    Some(end) => return Err(unexpected_end(end)),
+                    None => {
+                        return Err(TokenError {
+                            error: CalcError::UnterminatedLoop,
+                            span,
+                        })
+                    }
+                };
+                nested.push((Repeat(unspanned(body)), span.start..end));
+            }
+            Else | Then | Loop => return Ok((nested, Some((token, span)))),
+            token => nested.push((token, span)),
+        }
+    }
+    Ok((nested, None))
+}
+
+/// Drop the spans of `tokens` that have been grouped into a block.
+fn unspanned(tokens: Vec<(Token, Range<usize>)>) -> Vec<Token> {
+    tokens.into_iter().map(|(token, _)| token).collect()
+}
+
+/// How deeply user defined words and blocks may be nested while evaluating before it is
+/// aborted.
+const MAX_WORD_DEPTH: usize = 256;
+
+/// How deeply `if` and `do` blocks may be nested within each other.
+const MAX_BLOCK_DEPTH: usize = 64;
+
+/// How many numbered registers there are.
+const REGISTERS: usize = 100;
+
+/// How many lines `undo` can revert.
+const MAX_UNDO: usize = 100;
+
+/// The most bits that a result may have unless `maxbits` changes it. This is about 1.26 million
+/// decimal digits, and multiplying numbers this big takes around a second.
+const DEFAULT_MAX_BITS: usize = 1 << 22;
+
+/// A handle that interrupts the line a [`Calculator`] is evaluating, from another thread or a
+/// signal handler. Get one with [`Calculator::interrupt_handle`].
+#[derive(Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    /// Stop the line being evaluated, which then fails and leaves the stack as it was.
+    ///
+    /// This only sets a flag, so it's safe to call from a signal handler.
+    pub fn interrupt(&self) {
+        self.0.store(true, AtomicOrdering::SeqCst);
+    }
+
+    /// Forget an interrupt that came before the line that is about to be evaluated.
+    fn clear(&self) {
+        self.0.store(false, AtomicOrdering::SeqCst);
+    }
+
+    /// Fail if the line has been interrupted. Evaluation checks this between tokens, and long
+    /// running primitives check it as they go.
+    pub(crate) fn check(&self) -> Result<(), CalcError> {
+        if self.0.load(AtomicOrdering::SeqCst) {
+            Err(CalcError::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The stacks other than the one in use.
+#[derive(Clone, PartialEq)]
+struct Stacks {
+    /// The name of the stack in use.
+    current: String,
+    /// The others, by name. Stacks are created empty when they're first switched to.
+    others: BTreeMap<String, Vec<Quantity>>,
+}
+
+impl Default for Stacks {
+    fn default() -> Self {
+        Stacks {
+            current: "main".to_string(),
+            others: BTreeMap::new(),
+        }
+    }
+}
+
+/// The state that `undo` and `redo` restore.
+#[derive(Default)]
+struct Snapshot {
+    stack: Vec<Quantity>,
+    stacks: Stacks,
+    variables: BTreeMap<String, Quantity>,
+    registers: Vec<Quantity>,
+}
+
+/// Something a line does outside of the calculator, which waits until the whole line has worked.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+enum Effect {
+    /// Write a session to a file.
+    Save(String, String),
+    /// Copy text to the clipboard.
+    Copy(String),
+}
+
+/// Write the file or copy the text of `effect`.
+#[cfg(feature = "std")]
+fn perform(effect: Effect) -> Result<(), CalcError> {
+    match effect {
+        Effect::Save(path, session) => fs::write(&path, session)
+            .map_err(|e| CalcError::Io(format!("could not write {}: {}", path, e))),
+        Effect::Copy(text) => clipboard::copy(&text),
+    }
+}
+
+/// Fail, as files and the clipboard need an operating system.
+#[cfg(not(feature = "std"))]
+fn perform(_: Effect) -> Result<(), CalcError> {
+    Err(CalcError::Io(
+        "Files and the clipboard need the std feature".into(),
+    ))
+}
+
+/// Evaluates lines of input against a stack of rationals.
+#[derive(Default)]
+pub struct Calculator {
+    stack: Vec<Quantity>,
+    stacks: Stacks,
+    settings: Settings,
+    variables: BTreeMap<String, Quantity>,
+    /// The numbered registers, up to the highest one that has been stored to. The rest are 0.
+    registers: Vec<Quantity>,
+    words: BTreeMap<String, Vec<Token>>,
+    formatter: Formatter,
+    output: Vec<String>,
+    /// The state from before the last line, for rolling back errors and for `show changed`.
+    previous: Rc<Snapshot>,
+    /// States from before recent lines, oldest first.
+    undo: VecDeque<Rc<Snapshot>>,
+    /// States reverted by `undo`, oldest first.
+    redo: Vec<Rc<Snapshot>>,
+    /// Whether the current line has used `undo` or `redo`.
+    rewound: bool,
+    /// Constants computed so far, with the precision they were computed to. They are only
+    /// recomputed when the precision is raised.
+    constants: BTreeMap<Constant, (usize, Int)>,
+    /// The iteration of each `do` loop that is running, innermost last.
+    iterations: Vec<usize>,
+    /// The source of `rand` and `randint`, seeded from the system the first time it's used
+    /// unless `seed` sets it.
+    rng: Option<StdRng>,
+    /// Whether to print each token and the stack after it, as set by `trace`.
+    trace: bool,
+    /// The top of the stack after each line that changed it, for `$1`, `$2` and so on.
+    results: Vec<Quantity>,
+    /// Whether `browse` has been used since the front end last checked.
+    browse: bool,
+    /// The key bindings from the last `set keymap` since the front end last checked.
+    keymap: Option<Keymap>,
+    /// The lines that `trace` has printed for the current line, which are kept if it fails.
+    trace_lines: Vec<String>,
+    /// The effects of the token being computed, for `evaluate` to perform after the line.
+    effects: Vec<Effect>,
+    /// Set to stop the line being evaluated. Sessions loaded by a line share it.
+    interrupt: Interrupt,
+}
+
+impl Calculator {
+    /// The stack, bottom first.
+    pub fn stack(&self) -> &[Quantity] {
+        &self.stack
+    }
+
+    /// The name of the stack in use.
+    pub fn stack_name(&self) -> &str {
+        &self.stacks.current
+    }
+
+    /// A handle for interrupting the lines that this calculator evaluates.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Take the text printed by commands like `help` since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        core::mem::take(&mut self.output)
+    }
+
+    /// Whether `browse` has been used since the last call.
+    pub fn take_browse(&mut self) -> bool {
+        core::mem::take(&mut self.browse)
+    }
+
+    /// The key bindings from the last `set keymap` since the last call, if any.
+    pub fn take_keymap(&mut self) -> Option<Keymap> {
+        self.keymap.take()
+    }
+
+    /// Replace the stack with `stack`, as an edit that `undo` can revert.
+    pub fn replace_stack(&mut self, stack: Vec<Quantity>) {
+        if stack == self.stack {
+            return;
+        }
+        self.previous = self.snapshot();
+        self.stack = stack;
+        self.undo.push_back(self.previous.clone());
+        if self.undo.len() > MAX_UNDO {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// The names of the user defined words and variables.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.words
+            .keys()
+            .chain(self.variables.keys())
+            .map(String::as_str)
+    }
+
+    /// The current modes.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// How the stack is displayed.
+    pub fn formatter(&self) -> &Formatter {
+        &self.formatter
+    }
+
+    /// The entries at the top of the stack that `show` selects for display.
+    pub fn shown(&self) -> &[Quantity] {
+        let start = match self.formatter.show {
+            Show::All => 0,
+            Show::Top => self.stack.len().saturating_sub(1),
+            Show::Changed => self
+                .stack
+                .iter()
+                .zip(&self.previous.stack)
+                .take_while(|(num, previous)| num == previous)
+                .count(),
+        };
+        &self.stack[start..]
+    }
+
+    /// Render the entries selected by `show`, one line each.
+    pub fn render(&self) -> Vec<String> {
+        let start = self.stack.len() - self.shown().len();
+        self.formatter.render(&self.stack, start, &self.settings)
+    }
+
+    /// Render `num` for display with the current formatter and settings.
+    pub fn format(&self, num: &Rational) -> String {
+        self.formatter.format(num, &self.settings)
+    }
+
+    /// Parse a line into tokens and compute them.
+    ///
+    /// If the line has an error, the stack, variables, registers, words and modes are left as
+    /// they were. Every token that can't be read is reported, and otherwise the first error in
+    /// evaluating the line. In `lenient` mode, tokens that can't be read are skipped instead.
+    pub fn parse(&mut self, word: &str) -> Result<(), Vec<TokenError>> {
+        self.interrupt.clear();
+        self.parse_line(word)
+    }
+
+    /// [`parse`](Calculator::parse) a line without forgetting an interrupt, for lines that are
+    /// evaluated as part of another one.
+    fn parse_line(&mut self, word: &str) -> Result<(), Vec<TokenError>> {
+        self.previous = self.snapshot();
+        self.rewound = false;
+        self.trace_lines.clear();
+        // Read the whole line before giving up on it, so that it can be fixed in one go.
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for token in Token::lex(word, self.settings.max_bits) {
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(error) if self.settings.lenient => {
+                    // The span may be part of a word, but the whole word is skipped.
+                    let end = error.span.end.min(word.len());
+                    let start = word[..error.span.start.min(end)]
+                        .trim_end_matches(|c: char| !c.is_whitespace())
+                        .len();
+                    let rest = &word[end..];
+                    let end = end + rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    self.output
+                        .push(format!("skipped `{}`: {}", &word[start..end], error));
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // Some errors, like division by zero, can only be found during evaluation. Keep a copy
+        // of everything the line could change so that it has no effect when they happen.
+        let (undo, redo) = (self.undo.clone(), self.redo.clone());
+        let words = self.words.clone();
+        let (settings, formatter) = (self.settings.clone(), self.formatter.clone());
+        let (trace, rng, browse, keymap) = (self.trace, self.rng.clone(), self.browse, self.keymap);
+        let output_len = self.output.len();
+        let result = self.evaluate(tokens, word.len());
+        if result.is_err() {
+            self.iterations.clear();
+            self.effects.clear();
+            self.trace = trace;
+            self.rng = rng;
+            self.browse = browse;
+            self.keymap = keymap;
+            // Only the trace is kept of what the line printed, to show how it got to the error.
+            self.output.truncate(output_len);
+            self.output.append(&mut self.trace_lines);
+            self.stack = self.previous.stack.clone();
+            self.stacks = self.previous.stacks.clone();
+            self.variables = self.previous.variables.clone();
+            self.registers = self.previous.registers.clone();
+            self.words = words;
+            self.settings = settings;
+            self.formatter = formatter;
+            self.undo = undo;
+            self.redo = redo;
+        }
+        result.map_err(|error| vec![error])
+    }
+
+    /// Compute the `tokens` of a line `len` bytes long, leaving whatever they have changed so
+    /// far if it fails.
+    fn evaluate(
+        &mut self,
+        tokens: Vec<(Token, Range<usize>)>,
+        len: usize,
+    ) -> Result<(), TokenError> {
+        let tokens = self.define_words(nest(tokens)?)?;
+        // We check for stack exhaustion before attempting to run anything.
+        // that way we don't end up with a half-evaluated expression.
+        self.check_stack_exhaustion(tokens.iter().map(|(token, _)| token))
+            .map_err(|error| TokenError {
+                error,
+                span: 0..len,
+            })?;
+        let mut effects = Vec::new();
+        for (token, span) in tokens {
+            self.compute(token, 0).map_err(|error| TokenError {
+                error,
+                span: span.clone(),
+            })?;
+            effects.extend(self.effects.drain(..).map(|effect| (effect, span.clone())));
+        }
+        for (effect, span) in effects {
+            perform(effect).map_err(|error| TokenError { error, span })?;
+        }
+        let changed = self.stack != self.previous.stack
+            || self.stacks != self.previous.stacks
+            || self.variables != self.previous.variables
+            || self.registers != self.previous.registers;
+        if changed && !self.rewound {
+            self.undo.push_back(self.previous.clone());
+            if self.undo.len() > MAX_UNDO {
+                self.undo.pop_front();
+            }
+            self.redo.clear();
+        }
+        // Lines that leave the stack alone, like mode changes, don't have a result of their own.
+        if self.stack != self.previous.stack && !self.rewound {
+            if let Some(num) = self.stack.last() {
+                self.results.push(num.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the modes, user defined words, variables and stack as lines of input that recreate
+    /// them.
+    pub fn session(&self) -> String {
+        let mut lines = vec![
+            "# rcalc session".to_string(),
+            WordSize(self.settings.word_size).to_string(),
+            Precision(self.settings.precision).to_string(),
+            MaxBits(self.settings.max_bits).to_string(),
+            Lenient(self.settings.lenient).to_string(),
+            Angles(self.settings.angle).to_string(),
+            Radix(self.formatter.base).to_string(),
+            Style(self.formatter.notation).to_string(),
+            Group(self.formatter.separator).to_string(),
+            Visible(self.formatter.show).to_string(),
+            Listing(self.formatter.order).to_string(),
+        ];
+        for (name, body) in &self.words {
+            let body: Vec<String> = body.iter().map(Token::to_string).collect();
+            lines.push(format!(": {} {} ;", name, body.join(" ")));
+        }
+        for (name, num) in &self.variables {
+            // Storing leaves the value on the stack, so drop it again.
+            lines.push(format!("{} ={} drop", Measure(num.clone()), name));
+        }
+        for (register, num) in self.registers.iter().enumerate() {
+            if *num != Rational::zero() {
+                lines.push(format!("{} sto {} drop", Measure(num.clone()), register));
+            }
+        }
+        let entries = |stack: &[Quantity]| {
+            let entries: Vec<String> = stack
+                .iter()
+                .map(|num| Measure(num.clone()).to_string())
+                .collect();
+            entries.join(" ")
+        };
+        for (name, stack) in &self.stacks.others {
+            lines.push(Switch(name.clone()).to_string());
+            if !stack.is_empty() {
+                lines.push(entries(stack));
+            }
+        }
+        if self.stacks != Stacks::default() {
+            lines.push(Switch(self.stacks.current.clone()).to_string());
+        }
+        if !self.stack.is_empty() {
+            lines.push(entries(&self.stack));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Replace the session with the one saved in `path`.
+    #[cfg(feature = "std")]
+    fn load(&mut self, path: &str) -> Result<(), CalcError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| CalcError::Io(format!("could not read {}: {}", path, e)))?;
+        self.load_session(path, &text)
+    }
+
+    /// Replace the session with `text`, as written by [`session`](Calculator::session).
+    ///
+    /// `name` says where the text came from in error messages.
+    pub fn load_session(&mut self, name: &str, text: &str) -> Result<(), CalcError> {
+        // Evaluate the text separately, so that an error partway through has no effect.
+        let mut session = Calculator {
+            interrupt: self.interrupt.clone(),
+            ..Calculator::default()
+        };
+        for (line_offset, line) in text.lines().enumerate() {
+            if let Err(mut errors) = session.parse_line(line) {
+                let error = errors.remove(0);
+                // An interrupt stops the line that loaded the session, not just the session.
+                if error.error == CalcError::Interrupted {
+                    return Err(CalcError::Interrupted);
+                }
+                let message = format!("{}:{}: {}", name, line_offset + 1, error);
+                return Err(CalcError::InvalidSession(message));
+            }
+        }
+        self.stack = session.stack;
+        self.stacks = session.stacks;
+        self.variables = session.variables;
+        self.registers = session.registers;
+        self.words = session.words;
+        self.settings = session.settings;
+        self.formatter = session.formatter;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Rc<Snapshot> {
+        Rc::new(Snapshot {
+            stack: self.stack.clone(),
+            stacks: self.stacks.clone(),
+            variables: self.variables.clone(),
+            registers: self.registers.clone(),
+        })
+    }
+
+    /// Replace the stack, variables and registers with `snapshot`, returning what they were.
+    fn restore(&mut self, snapshot: Rc<Snapshot>) -> Rc<Snapshot> {
+        let current = self.snapshot();
+        self.stack = snapshot.stack.clone();
+        self.stacks = snapshot.stacks.clone();
+        self.variables = snapshot.variables.clone();
+        self.registers = snapshot.registers.clone();
+        self.rewound = true;
+        current
+    }
+
+    /// Register the `: name ... ;` definitions in `tokens`, returning the tokens outside of them.
+    fn define_words(
+        &mut self,
+        tokens: Vec<(Token, Range<usize>)>,
+    ) -> Result<Vec<(Token, Range<usize>)>, TokenError> {
+        let error = |error: CalcError, span: Range<usize>| TokenError { error, span };
+        let mut rest = Vec::new();
+        let mut definitions = Vec::new();
+        let mut tokens = tokens.into_iter();
+        while let Some((token, span)) = tokens.next() {
+            match token {
+                Define => {
+                    let name = match tokens.next() {
+                        Some((Recall(name), _)) => name,
+                        Some((_, span)) => return Err(error(CalcError::ExpectedWordName, span)),
+                        None => return Err(error(CalcError::ExpectedWordName, span)),
+                    };
+                    let mut body = Vec::new();
+                    loop {
+                        match tokens.next() {
+                            Some((EndDefine, _)) => break,
+                            Some((Define, span)) => {
+                                return Err(error(CalcError::NestedDefinition, span))
+                            }
+                            Some((token, _)) => body.push(token),
+                            None => return Err(error(CalcError::UnterminatedDefinition, span)),
+                        }
+                    }
+                    definitions.push((name, body));
+                }
+                EndDefine => return Err(error(CalcError::UnexpectedEndDefinition, span)),
+                token => rest.push((token, span)),
+            }
+        }
+        self.words.extend(definitions);
+        Ok(rest)
+    }
+
+    /// Evaluate a single token against the stack, tracing it if `trace` is on.
+    ///
+    /// `depth` counts how many user defined words and blocks the token is nested within.
+    fn compute(&mut self, token: Token, depth: usize) -> Result<(), CalcError> {
+        self.interrupt.check()?;
+        if !self.trace {
+            return self.apply(token, depth);
+        }
+        let name = token.to_string();
+        self.apply(token, depth)?;
+        // `trace off` turns itself off before it would be printed.
+        if self.trace {
+            let entries: Vec<String> = self
+                .stack
+                .iter()
+                .map(|num| self.formatter.quantity(num, &self.settings))
+                .collect();
+            let indent = "  ".repeat(depth);
+            let line = format!("{}{} -> {}", indent, name, entries.join(" "));
+            self.trace_lines.push(line.clone());
+            self.output.push(line);
+        }
+        Ok(())
+    }
+
+    /// Evaluate a single token against the stack.
+    fn apply(&mut self, token: Token, depth: usize) -> Result<(), CalcError> {
+        match token {
+            Duplicate => {
+                let num = self.pop_quantity()?;
+                self.stack.push(num.clone());
+                self.stack.push(num);
+            }
+            Empty => self.stack.clear(),
+            Depth => self.push(Rational::from(self.stack.len())),
+            PastResult(index) => match self.results.get(index - 1) {
+                Some(num) => self.stack.push(num.clone()),
+                None => return Err(CalcError::NoResult(index)),
+            },
+            Results => {
+                for (index, num) in self.results.iter().enumerate() {
+                    let num = self.formatter.quantity(num, &self.settings);
+                    self.output.push(format!("${}: {}", index + 1, num));
+                }
+            }
+            Answer => match self.previous.stack.last() {
+                Some(num) => self.stack.push(num.clone()),
+                None => return Err(CalcError::NoAnswer),
+            },
+            Sum | Product => {
+                let mut entries = core::mem::take(&mut self.stack).into_iter();
+                let (settings, interrupt) = (&self.settings, &self.interrupt);
+                let total = match (entries.next(), &token) {
+                    (Some(first), Sum) => entries.try_fold(first, |total, num| {
+                        interrupt.check()?;
+                        total.combine(num, |a, b| a + b)
+                    })?,
+                    (Some(first), _) => entries.try_fold(first, |total, num| {
+                        interrupt.check()?;
+                        settings.check_bits(quantity_bits(&total) + quantity_bits(&num))?;
+                        total.multiply(num)
+                    })?,
+                    (None, Sum) => Rational::zero().into(),
+                    (None, _) => Rational::one().into(),
+                };
+                self.push_quantity(total);
+            }
+            Min | Max => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                let keep_lhs = match lhs.compare(&rhs)? {
+                    Ordering::Less => token == Min,
+                    Ordering::Greater => token == Max,
+                    Ordering::Equal => true,
+                };
+                self.stack.push(if keep_lhs { lhs } else { rhs });
+            }
+            MinAll | MaxAll => {
+                let mut entries = core::mem::take(&mut self.stack).into_iter();
+                if let Some(first) = entries.next() {
+                    let extreme = entries.try_fold(first, |extreme, num| {
+                        let ordering = num.compare(&extreme)?;
+                        let better = match token {
+                            MinAll => ordering.is_lt(),
+                            _ => ordering.is_gt(),
+                        };
+                        Ok::<_, CalcError>(if better { num } else { extreme })
+                    })?;
+                    self.stack.push(extreme);
+                }
+            }
+            Reverse => self.stack.reverse(),
+            Sort | ReverseSort => {
+                // Entries that can't be compared, like a date and a number, fail the line. Those
+                // that can be are all alike, so it's enough to compare each with the first.
+                if let Some(first) = self.stack.first() {
+                    for entry in &self.stack {
+                        first.compare(entry)?;
+                    }
+                }
+                if token == Sort {
+                    self.stack.sort_by_cached_key(Quantity::base_value);
+                } else {
+                    self.stack
+                        .sort_by_cached_key(|entry| core::cmp::Reverse(entry.base_value()));
+                }
+            }
+            Random => {
+                let scale = Int::from(10).pow(self.settings.precision);
+                let num = self.rng().gen_uint_below(&scale);
+                self.push(Rational::new(num, scale));
+            }
+            RandomInt => {
+                let hi = self.pop()?;
+                let lo = self.pop()?;
+                let (lo, hi) = (to_integer(lo)?, to_integer(hi)?);
+                if lo > hi {
+                    return Err(CalcError::EmptyRange);
+                }
+                let num = self.rng().gen_int_range(&lo, &(hi + 1));
+                self.push(num.into());
+            }
+            Seed(seed) => self.rng = Some(StdRng::seed_from_u64(seed)),
+            Summary(statistic) => {
+                let entries = core::mem::take(&mut self.stack);
+                self.summarize(statistic, entries)?;
+            }
+            SummaryOf(statistic) => {
+                let count = self.pop()?;
+                let start = self.count_start(&count)?;
+                let entries = self.stack.split_off(start);
+                self.summarize(statistic, entries)?;
+            }
+            Drop => {
+                self.pop_quantity()?;
+            }
+            Swap => {
+                let top = self.pop_quantity()?;
+                let second = self.pop_quantity()?;
+                self.stack.push(top);
+                self.stack.push(second);
+            }
+            Over => {
+                let top = self.pop_quantity()?;
+                let second = self.pop_quantity()?;
+                self.stack.push(second.clone());
+                self.stack.push(top);
+                self.stack.push(second);
+            }
+            Nip => {
+                let top = self.pop_quantity()?;
+                self.pop_quantity()?;
+                self.stack.push(top);
+            }
+            Tuck => {
+                let top = self.pop_quantity()?;
+                let second = self.pop_quantity()?;
+                self.stack.push(top.clone());
+                self.stack.push(second);
+                self.stack.push(top);
+            }
+            Rot => {
+                let top = self.pop_quantity()?;
+                let second = self.pop_quantity()?;
+                let third = self.pop_quantity()?;
+                self.stack.push(second);
+                self.stack.push(top);
+                self.stack.push(third);
+            }
+            Pick => {
+                let count = self.pop()?;
+                let index = self.stack_index(&count)?;
+                self.stack.push(self.stack[index].clone());
+            }
+            Roll => {
+                let count = self.pop()?;
+                let index = self.stack_index(&count)?;
+                let num = self.stack.remove(index);
+                self.stack.push(num);
+            }
+            DropN => {
+                let count = self.pop()?;
+                let start = self.count_start(&count)?;
+                self.stack.truncate(start);
+            }
+            DupN => {
+                let count = self.pop()?;
+                let start = self.count_start(&count)?;
+                self.stack.extend_from_within(start..);
+            }
+            Keep => {
+                let count = self.pop()?;
+                let start = self.count_start(&count)?;
+                self.stack.drain(..start);
+            }
+            Number(n) => self.push(n),
+            Measure(quantity) => {
+                self.settings.check_bits(quantity_bits(&quantity))?;
+                self.push_quantity(quantity);
+            }
+            Const(constant) => {
+                let num = self.constant(constant)?;
+                self.push(num);
+            }
+            Plus => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                self.push_quantity(lhs.add(rhs)?);
+            }
+            Minus => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                self.push_quantity(lhs.subtract(rhs)?);
+            }
+            Times => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                self.settings
+                    .check_bits(quantity_bits(&lhs) + quantity_bits(&rhs))?;
+                self.push_quantity(lhs.multiply(rhs)?);
+            }
+            Divide => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                if rhs.value.is_zero() {
+                    return Err(CalcError::DivisionByZero);
+                }
+                self.settings
+                    .check_bits(quantity_bits(&lhs) + quantity_bits(&rhs))?;
+                self.push_quantity(lhs.divide(rhs)?);
+            }
+            Modulo => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                if rhs.value.is_zero() {
+                    return Err(CalcError::DivisionByZero);
+                }
+                self.push_quantity(lhs.combine(rhs, modulo)?);
+            }
+            Exp => {
+                let rhs = self.pop()?;
+                let lhs = self.pop_quantity()?;
+                // A power of an n bit number has up to n bits for each time it's multiplied,
+                // except that powers of 0, 1 and -1 never grow.
+                let times = rhs.clone().round().abs();
+                let times = if times > usize::MAX {
+                    usize::MAX
+                } else {
+                    usize::from(&times)
+                };
+                let bits = quantity_bits(&lhs);
+                if bits > 1 {
+                    self.settings.check_bits(bits.saturating_mul(times))?;
+                }
+                self.push_quantity(lhs.pow(rhs, &self.interrupt)?);
+            }
+            Gcd => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let (lhs, rhs) = (to_integer(lhs)?, to_integer(rhs)?);
+                self.push(Rational::new(lhs.gcd(&rhs).abs(), 1.into()));
+            }
+            IsPrime => {
+                let num = self.pop()?;
+                let prime = primes::is_prime(&to_integer(num)?);
+                self.push(Rational::from(u32::from(prime)));
+            }
+            PowerMod => {
+                let modulus = self.pop()?;
+                let exponent = self.pop()?;
+                let base = self.pop()?;
+                let (base, exponent) = (to_integer(base)?, to_integer(exponent)?);
+                let modulus = to_modulus(modulus)?;
+                if exponent < 0 {
+                    return Err(CalcError::NegativeExponent);
+                }
+                // Reduce the base first, so that the result isn't negative.
+                let base = (base % &modulus + &modulus) % &modulus;
+                self.push(Rational::new(base.pow_mod(&exponent, &modulus), Int::one()));
+            }
+            InverseMod => {
+                let modulus = self.pop()?;
+                let num = self.pop()?;
+                let modulus = to_modulus(modulus)?;
+                let inverse =
+                    inverse_mod(to_integer(num)?, &modulus).ok_or(CalcError::NoInverse)?;
+                self.push(Rational::new(inverse, Int::one()));
+            }
+            NextPrime => {
+                let num = self.pop()?;
+                let prime = primes::next_prime(&to_integer(num)?, &self.interrupt)?;
+                self.push(Rational::new(prime, Int::one()));
+            }
+            PreviousPrime => {
+                let num = self.pop()?;
+                let prime = primes::previous_prime(&to_integer(num)?, &self.interrupt)?
+                    .ok_or(CalcError::NoPreviousPrime)?;
+                self.push(Rational::new(prime, Int::one()));
+            }
+            Factor => {
+                let num = self.pop()?;
+                let num = to_integer(num)?;
+                if num < 2 {
+                    return Err(CalcError::InvalidFactor);
+                }
+                for factor in primes::factor(num, &self.interrupt)? {
+                    self.push(Rational::new(factor, Int::one()));
+                }
+            }
+            Lcm => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let (lhs, rhs) = (to_integer(lhs)?, to_integer(rhs)?);
+                self.push(Rational::new(lcm(&lhs, &rhs), 1.into()));
+            }
+            And => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = self.settings.to_word(lhs) & self.settings.to_word(rhs);
+                self.push(self.settings.wrap_result(result));
+            }
+            Or => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = self.settings.to_word(lhs) | self.settings.to_word(rhs);
+                self.push(self.settings.wrap_result(result));
+            }
+            Xor => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let result = self.settings.to_word(lhs) ^ self.settings.to_word(rhs);
+                self.push(self.settings.wrap_result(result));
+            }
+            Not => {
+                let num = self.pop()?;
+                let result = -self.settings.to_word(num) - 1;
+                self.push(self.settings.wrap_result(result));
+            }
+            ShiftLeft => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let count = shift_count(&rhs)?;
+                let word = self.settings.to_word(lhs);
+                self.settings
+                    .check_bits((word.bit_length() as usize).saturating_add(count))?;
+                let result = word << count;
+                self.push(self.settings.wrap_result(result));
+            }
+            BitSet => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let index = self.settings.bit_index(&rhs)?;
+                let result = self.settings.to_word(lhs) | (Int::one() << index as usize);
+                self.push(self.settings.wrap_result(result));
+            }
+            BitClear => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let index = self.settings.bit_index(&rhs)?;
+                let mask = -(Int::one() << index as usize) - 1;
+                let result = self.settings.to_word(lhs) & mask;
+                self.push(self.settings.wrap_result(result));
+            }
+            BitToggle => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let index = self.settings.bit_index(&rhs)?;
+                let result = self.settings.to_word(lhs) ^ (Int::one() << index as usize);
+                self.push(self.settings.wrap_result(result));
+            }
+            BitTest => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let index = self.settings.bit_index(&rhs)?;
+                let set = self.settings.to_word(lhs).bit(index);
+                self.push(Rational::from(u32::from(set)));
+            }
+            Mask => {
+                let shift = self.pop()?;
+                let width = self.pop()?;
+                let (width, shift) = (shift_count(&width)?, shift_count(&shift)?);
+                self.settings.check_bits(width.saturating_add(shift))?;
+                let result = ((Int::one() << width) - 1) << shift;
+                self.push(self.settings.wrap_result(result));
+            }
+            MaskRange => {
+                let low = self.pop()?;
+                let high = self.pop()?;
+                let (high, low) = self.settings.bit_range(&high, &low)?;
+                let result = ((Int::one() << (high - low + 1) as usize) - 1) << low as usize;
+                self.push(self.settings.wrap_result(result));
+            }
+            BitExtract => {
+                let low = self.pop()?;
+                let high = self.pop()?;
+                let num = self.pop()?;
+                let (high, low) = self.settings.bit_range(&high, &low)?;
+                let mask = (Int::one() << (high - low + 1) as usize) - 1;
+                // The field is unsigned, so it isn't wrapped back into the word.
+                let field = shift_right(self.settings.to_word(num), low as usize) & mask;
+                self.push(Rational::new(field, Int::one()));
+            }
+            BitInsert => {
+                let low = self.pop()?;
+                let high = self.pop()?;
+                let field = self.pop()?;
+                let num = self.pop()?;
+                let (high, low) = self.settings.bit_range(&high, &low)?;
+                let mask = (Int::one() << (high - low + 1) as usize) - 1;
+                // Bits of the field that don't fit are dropped, as a register would.
+                let field = (self.settings.to_word(field) & &mask) << low as usize;
+                let cleared = self.settings.to_word(num) & (-(mask << low as usize) - 1);
+                self.push(self.settings.wrap_result(cleared | field));
+            }
+            PopCount => {
+                let num = self.pop()?;
+                let ones = self.settings.to_unsigned(num)?.count_ones();
+                self.push(Rational::from(ones));
+            }
+            LeadingZeros => {
+                let num = self.pop()?;
+                let bits = self.settings.word_size.ok_or(CalcError::NeedsWordSize)?;
+                let num = self.settings.to_unsigned(num)?;
+                let used = if num == 0 { 0 } else { num.bit_length() };
+                self.push(Rational::from(bits - used));
+            }
+            TrailingZeros => {
+                let num = self.pop()?;
+                let num = self.settings.to_unsigned(num)?;
+                let zeros = match self.settings.word_size {
+                    _ if num != 0 => num.trailing_zeros(),
+                    Some(bits) => bits,
+                    None => return Err(CalcError::NeedsWordSize),
+                };
+                self.push(Rational::from(zeros));
+            }
+            ByteSwap(width) => {
+                let num = self.pop()?;
+                let bits = width
+                    .or(self.settings.word_size)
+                    .ok_or(CalcError::NeedsWordSize)?;
+                if bits % 8 != 0 {
+                    return Err(CalcError::PartialBytes);
+                }
+                let modulus = Int::one() << bits as usize;
+                let mut num = num.round() % &modulus;
+                if num < 0 {
+                    num += modulus;
+                }
+                let mut swapped = Int::zero();
+                for _ in 0..bits / 8 {
+                    swapped = (swapped << 8) | (&num & Int::from(0xff));
+                    num >>= 8;
+                }
+                self.push(self.settings.wrap_result(swapped));
+            }
+            ShiftRight => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let count = shift_count(&rhs)?;
+                let result = shift_right(self.settings.to_word(lhs), count);
+                self.push(self.settings.wrap_result(result));
+            }
+            Round => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| num.round().into())?);
+            }
+            Abs => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| num.abs())?);
+            }
+            Negate => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| -num)?);
+            }
+            Invert => {
+                let num = self.pop()?;
+                if num.is_zero() {
+                    return Err(CalcError::DivisionByZero);
+                }
+                self.push(num.invert());
+            }
+            Floor => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| floor(num).into())?);
+            }
+            Ceil => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| ceil(num).into())?);
+            }
+            Trunc => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| trunc(num).into())?);
+            }
+            Frac => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| {
+                    let whole = Rational::from(trunc(num.clone()));
+                    num - whole
+                })?);
+            }
+            Sqrt => {
+                let num = self.pop()?;
+                let precision = self.settings.precision;
+                let bits = approx::root_bits(&num, 2, precision);
+                self.settings.check_bits(bits)?;
+                match approx::sqrt(num, precision, &self.interrupt)? {
+                    Some(root) => self.push(root),
+                    None => return Err(CalcError::NegativeRoot),
+                }
+            }
+            Root => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let index = match to_count(&rhs) {
+                    Some(index) if index > 0 => index,
+                    _ => return Err(CalcError::InvalidRootIndex),
+                };
+                let precision = self.settings.precision;
+                let bits = approx::root_bits(&lhs, index, precision);
+                self.settings.check_bits(bits)?;
+                match approx::root(lhs, index, precision, &self.interrupt)? {
+                    Some(root) => self.push(root),
+                    None => return Err(CalcError::NegativeRoot),
+                }
+            }
+            Sin => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::sin(num, angle, precision, &self.interrupt)?);
+            }
+            Cos => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::cos(num, angle, precision, &self.interrupt)?);
+            }
+            Tan => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::tan(num, angle, precision, &self.interrupt)?);
+            }
+            Asin => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::asin(num, angle, precision, &self.interrupt)?);
+            }
+            Acos => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::acos(num, angle, precision, &self.interrupt)?);
+            }
+            Atan => {
+                let num = self.pop()?;
+                let (angle, precision) = (self.settings.angle, self.settings.precision);
+                self.push(elementary::atan(num, angle, precision, &self.interrupt)?);
+            }
+            Exponential => {
+                let num = self.pop()?;
+                let precision = self.settings.precision;
+                self.push(elementary::exp(num, precision, &self.interrupt)?);
+            }
+            Ln => {
+                let num = self.pop()?;
+                let precision = self.settings.precision;
+                self.push(elementary::ln(num, precision, &self.interrupt)?);
+            }
+            Log2 => {
+                let num = self.pop()?;
+                let precision = self.settings.precision;
+                let log = elementary::log(num, Rational::from(2), precision, &self.interrupt)?;
+                self.push(log);
+            }
+            Log10 => {
+                let num = self.pop()?;
+                let precision = self.settings.precision;
+                let log = elementary::log(num, Rational::from(10), precision, &self.interrupt)?;
+                self.push(log);
+            }
+            Logb => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                let precision = self.settings.precision;
+                self.push(elementary::log(lhs, rhs, precision, &self.interrupt)?);
+            }
+            Factorial => {
+                let num = self.pop()?;
+                self.push(factorial(&num, &self.settings)?.into());
+            }
+            Choose => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(choose(lhs, rhs, &self.settings, &self.interrupt)?.into());
+            }
+            Permute => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.push(permute(lhs, rhs, &self.settings, &self.interrupt)?.into());
+            }
+            Percent => {
+                let num = self.pop_quantity()?;
+                self.push_quantity(num.map(|num| num / Rational::from(100))?);
+            }
+            PercentOf => {
+                let rhs = self.pop()?;
+                let lhs = self.pop_quantity()?;
+                self.push_quantity(lhs.map(|num| num * rhs / Rational::from(100))?);
+            }
+            Less | Greater | LessEqual | GreaterEqual | Equal | NotEqual => {
+                let rhs = self.pop_quantity()?;
+                let lhs = self.pop_quantity()?;
+                let ordering = lhs.compare(&rhs)?;
+                let holds = match token {
+                    Less => ordering.is_lt(),
+                    Greater => ordering.is_gt(),
+                    LessEqual => ordering.is_le(),
+                    GreaterEqual => ordering.is_ge(),
+                    Equal => ordering.is_eq(),
+                    _ => ordering.is_ne(),
+                };
+                self.push(Rational::from(holds as u32));
+            }
+            Store(name) => {
+                let num = self.top()?.clone();
+                self.variables.insert(name, num);
+            }
+            StoreRegister(register) | AddToRegister(register) => {
+                let num = self.top()?.clone();
+                if self.registers.len() <= register {
+                    self.registers.resize(register + 1, Rational::zero().into());
+                }
+                let mut stored = match token {
+                    AddToRegister(_) => self.registers[register].clone().add(num)?,
+                    _ => num,
+                };
+                Rc::make_mut(&mut stored.value).normalize();
+                self.registers[register] = stored;
+            }
+            Switch(name) => {
+                if name != self.stacks.current {
+                    let stack = self.stacks.others.remove(&name).unwrap_or_default();
+                    let previous = core::mem::replace(&mut self.stack, stack);
+                    let current = core::mem::replace(&mut self.stacks.current, name);
+                    self.stacks.others.insert(current, previous);
+                }
+            }
+            Exchange(name) => {
+                let num = self.pop_quantity()?;
+                if name == self.stacks.current {
+                    self.stack.push(num);
+                } else {
+                    let other = self.stacks.others.entry(name).or_default();
+                    if let Some(theirs) = other.pop() {
+                        self.stack.push(theirs);
+                    }
+                    other.push(num);
+                }
+            }
+            RecallRegister(register) => {
+                let num = self.registers.get(register).cloned();
+                self.stack
+                    .push(num.unwrap_or_else(|| Rational::zero().into()));
+            }
+            Recall(name) => {
+                if let Some(body) = self.words.get(&name) {
+                    if depth >= MAX_WORD_DEPTH {
+                        return Err(CalcError::RecursionTooDeep(name));
+                    }
+                    for token in body.clone() {
+                        self.compute(token, depth + 1)?;
+                    }
+                } else if let Some(num) = self.variables.get(&name) {
+                    self.stack.push(num.clone());
+                } else if self.settings.lenient {
+                    let error = CalcError::Undefined(name.clone());
+                    self.output.push(format!("skipped `{}`: {}", name, error));
+                } else {
+                    return Err(CalcError::Undefined(name));
+                }
+            }
+            Define | EndDefine => return Err(CalcError::NestedDefinition),
+            If | Else | Then => return Err(CalcError::UnexpectedBranch),
+            Do | Loop => return Err(CalcError::UnexpectedLoop),
+            Infix(tokens) => {
+                for token in tokens {
+                    self.compute(token, depth)?;
+                }
+            }
+            Repeat(body) => {
+                let count = self.pop()?;
+                let count = to_count(&count).ok_or(CalcError::InvalidLoopCount)?;
+                self.iterations.push(0);
+                for iteration in 0..count {
+                    // The stack depth after the loop is only known ahead of time when the
+                    // count is a literal, so check each iteration before running it.
+                    self.check_stack_exhaustion(&body)?;
+                    *self.iterations.last_mut().unwrap() = iteration;
+                    for token in body.clone() {
+                        self.compute(token, depth + 1)?;
+                    }
+                }
+                self.iterations.pop();
+            }
+            Index => match self.iterations.last() {
+                Some(&iteration) => self.push(Rational::from(iteration)),
+                None => return Err(CalcError::NotInLoop),
+            },
+            IfElse(then, otherwise) => {
+                let flag = self.pop()?;
+                let branch = if flag.is_zero() { otherwise } else { then };
+                for token in branch {
+                    self.compute(token, depth + 1)?;
+                }
+            }
+            WordSize(bits) => self.settings.word_size = bits,
+            MaxBits(bits) => self.settings.max_bits = bits,
+            Precision(digits) => self.settings.precision = digits,
+            Radix(base) => self.formatter.base = base,
+            Style(notation) => self.formatter.notation = notation,
+            Angles(angle) => self.settings.angle = angle,
+            Visible(show) => self.formatter.show = show,
+            Listing(order) => self.formatter.order = order,
+            Group(separator) => self.formatter.separator = separator,
+            Trace(trace) => self.trace = trace,
+            Lenient(lenient) => self.settings.lenient = lenient,
+            Undo => {
+                let snapshot = self.undo.pop_back().ok_or(CalcError::NothingToUndo)?;
+                let current = self.restore(snapshot);
+                self.redo.push(current);
+            }
+            Redo => {
+                let snapshot = self.redo.pop().ok_or(CalcError::NothingToRedo)?;
+                let current = self.restore(snapshot);
+                self.undo.push_back(current);
+            }
+            Help(topic) => self.output.push(operators::help(topic.as_deref())),
+            Browse => self.browse = true,
+            SetKeymap(keymap) => self.keymap = Some(keymap),
+            Address(address, prefix) => {
+                self.push(Rational::from(address));
+                if let Some(prefix) = prefix {
+                    self.push(Rational::from(prefix));
+                }
+            }
+            Network | Broadcast => {
+                let prefix = self.pop()?;
+                let address = self.pop()?;
+                let (address, prefix) = (ipv4::to_address(&address)?, ipv4::to_prefix(&prefix)?);
+                let result = match token {
+                    Network => ipv4::network(address, prefix),
+                    _ => ipv4::broadcast(address, prefix),
+                };
+                self.push(Rational::from(result));
+            }
+            Hosts => {
+                let prefix = self.pop()?;
+                self.push(Rational::from(ipv4::hosts(ipv4::to_prefix(&prefix)?)));
+            }
+            InNetwork => {
+                let prefix = self.pop()?;
+                let network = self.pop()?;
+                let address = self.pop()?;
+                let prefix = ipv4::to_prefix(&prefix)?;
+                let inside = ipv4::network(ipv4::to_address(&address)?, prefix)
+                    == ipv4::network(ipv4::to_address(&network)?, prefix);
+                self.push(Rational::from(u32::from(inside)));
+            }
+            ShowAddress => {
+                let num = self.top()?.clone().into_number()?;
+                self.output.push(ipv4::format(ipv4::to_address(&num)?));
+            }
+            Approximate => {
+                let max = self.pop()?;
+                let num = self.pop()?;
+                let max = match max.into_parts() {
+                    (max, den) if den.is_one() && max > 0 => max,
+                    _ => return Err(CalcError::InvalidDenominator),
+                };
+                self.push(best_approximation(num, &max));
+            }
+            ShowContinuedFraction => {
+                let num = self.top()?.clone().into_number()?;
+                let terms: Vec<String> =
+                    continued_fraction(num).iter().map(Int::to_string).collect();
+                let output = match terms.split_first() {
+                    Some((first, [])) => format!("[{}]", first),
+                    Some((first, rest)) => format!("[{};{}]", first, rest.join(",")),
+                    None => unreachable!("every number has a whole part"),
+                };
+                self.output.push(output);
+            }
+            Bits => {
+                let num = self.top()?.clone().into_number()?;
+                let num = self.settings.to_unsigned(num)?;
+                // Without a word size, show just enough whole nibbles for the number.
+                let width = match self.settings.word_size {
+                    Some(bits) => bits,
+                    None => num.bit_length().div_ceil(4) * 4,
+                };
+                self.output.push(format::bits(&num, width));
+            }
+            Save(path) => self.effects.push(Effect::Save(path, self.session())),
+            #[cfg(feature = "std")]
+            Load(path) => self.load(&path)?,
+            #[cfg(not(feature = "std"))]
+            Load(_) => return Err(CalcError::Io("Files need the std feature".into())),
+            Copy(index) => {
+                let index = self.stack_index(&Rational::from(index.unwrap_or(0)))?;
+                let text = self.formatter.quantity(&self.stack[index], &self.settings);
+                self.effects.push(Effect::Copy(text));
+            }
+            Convert(unit) => {
+                let num = self.pop_quantity()?;
+                self.settings
+                    .check_bits(quantity_bits(&num) + bit_size(unit.scale()))?;
+                self.push_quantity(num.convert(&unit)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_stack_exhaustion<'a>(
+        &'a self,
+        stack: impl IntoIterator<Item = &'a Token>,
+    ) -> Result<(), CalcError> {
+        match self.stack_depth_after(self.stack.len(), stack, &[]) {
+            Some(_) => Ok(()),
+            None => Err(CalcError::StackUnderflow),
+        }
+    }
+
+    /// Simulate evaluating `tokens` on a stack `depth` entries deep, returning the resulting
+    /// depth, `Some(None)` if it can't be known until the tokens run, or `None` if the stack
+    /// would be exhausted.
+    ///
+    /// `expanding` names the user defined words that `tokens` are nested within.
+    fn stack_depth_after<'a>(
+        &'a self,
+        depth: usize,
+        tokens: impl IntoIterator<Item = &'a Token>,
+        expanding: &[&'a str],
+    ) -> Option<Option<usize>> {
+        // The rest of the tokens are left to compute once the depth is unknown.
+        macro_rules! known {
+            ($depth:expr) => {
+                match $depth? {
+                    Some(depth) => depth,
+                    None => return Some(None),
+                }
+            };
+        }
+        // The count argument of pick and roll is only known ahead of time when it's
+        // a literal, so we track the most recent one. Otherwise compute checks at runtime.
+        let mut delta = depth;
+        let mut literal = None;
+        for token in tokens {
+            delta = match token {
+                // User defined words have the combined effect of their definition. A word
+                // that calls itself is left to compute, which also limits the recursion.
+                Recall(name) if self.words.contains_key(name) => {
+                    if expanding.contains(&name.as_str()) {
+                        Some(delta)
+                    } else {
+                        let mut expanding = expanding.to_vec();
+                        expanding.push(name);
+                        Some(known!(self.stack_depth_after(
+                            delta,
+                            &self.words[name],
+                            &expanding
+                        )))
+                    }
+                }
+                // ( -- a)
+                Number(_) | Measure(_) | Const(_) | Duplicate | Recall(_) | Index | Depth
+                | Answer | PastResult(_) | Random | RecallRegister(_) => Some(delta + 1),
+                // ( -- a) or ( -- a n)
+                Address(_, prefix) => Some(delta + 1 + usize::from(prefix.is_some())),
+                // These replace the stack with a saved one. Only the most recent is checked,
+                // and compute reports it when there's nothing to restore.
+                Undo => Some(self.undo.back().map_or(delta, |s| s.stack.len())),
+                Redo => Some(self.redo.last().map_or(delta, |s| s.stack.len())),
+                // The loaded stack isn't known until the file is read, and the line may have
+                // changed the stack it switches to earlier on, so compute checks the rest.
+                Load(_) | Switch(_) => return Some(None),
+                // (a -- b), or (a -- ) when the other stack is empty.
+                Exchange(name) => {
+                    let delta = delta.checked_sub(1)?;
+                    match self.stacks.others.get(name) {
+                        Some(stack) if !stack.is_empty() => Some(delta + 1),
+                        _ if *name == self.stacks.current => Some(delta + 1),
+                        _ => Some(delta),
+                    }
+                }
+                // This weirdness, the subtract and then add, is an exact representation of the stack affects
+                // of all of these operators, (a b -- c). We must be able to pop 2 off the stack, but we later
+                // add 1 back.
+                Plus | Minus | Times | Divide | Modulo | Exp | Root | Logb | Gcd | Lcm | Choose
+                | Permute | PercentOf | Min | Max | RandomInt | Less | Greater | LessEqual
+                | GreaterEqual | Equal | NotEqual | Or | And | Xor | ShiftLeft | ShiftRight
+                | BitSet | BitClear | BitToggle | BitTest | Mask | MaskRange | Network
+                | Broadcast | Approximate | InverseMod => delta.checked_sub(2).map(|d| d + 1),
+                // (a -- b)
+                Round | Floor | Ceil | Trunc | Frac | Not | Abs | Negate | Invert | Sqrt
+                | Factorial | Convert(_) | Sin | Cos | Tan | Asin | Acos | Atan | Exponential
+                | Ln | Log2 | Log10 | Percent | PopCount | LeadingZeros | TrailingZeros
+                | ByteSwap(_) | Hosts | IsPrime | NextPrime | PreviousPrime => {
+                    delta.checked_sub(1).map(|d| d + 1)
+                }
+                // (xu ... x0 -- xu ... x0)
+                Copy(index) => delta
+                    .checked_sub(index.unwrap_or(0).saturating_add(1))
+                    .map(|_| delta),
+                // (a -- a)
+                Store(_)
+                | StoreRegister(_)
+                | AddToRegister(_)
+                | Bits
+                | ShowAddress
+                | ShowContinuedFraction => delta.checked_sub(1).map(|d| d + 1),
+                // (a b -- b a)
+                Swap => delta.checked_sub(2).map(|d| d + 2),
+                // (a b -- a b a)
+                Over => delta.checked_sub(2).map(|d| d + 3),
+                // (a b -- b)
+                Nip => delta.checked_sub(2).map(|d| d + 1),
+                // (a b -- b a b)
+                Tuck => delta.checked_sub(2).map(|d| d + 3),
+                Infix(tokens) => Some(known!(self.stack_depth_after(delta, tokens, expanding))),
+                // Either branch may run, so what follows must work after both.
+                IfElse(then, otherwise) => {
+                    let delta = delta.checked_sub(1)?;
+                    let then = known!(self.stack_depth_after(delta, then, expanding));
+                    Some(then.min(known!(self.stack_depth_after(delta, otherwise, expanding))))
+                }
+                // The body runs count times, which is only known when the count is a literal.
+                // Otherwise this assumes the fewest entries that it could leave.
+                Repeat(body) => {
+                    let delta = delta.checked_sub(1)?;
+                    let after = known!(self.stack_depth_after(delta, body, expanding));
+                    match literal {
+                        Some(0) => Some(delta),
+                        // Each iteration grows the stack by the same amount, so if the first
+                        // works then so does the last.
+                        Some(count) if after >= delta => {
+                            Some(delta.saturating_add((after - delta).saturating_mul(count)))
+                        }
+                        Some(count) => {
+                            let last =
+                                delta.checked_sub((delta - after).checked_mul(count - 1)?)?;
+                            Some(known!(self.stack_depth_after(last, body, expanding)))
+                        }
+                        None if after >= delta => Some(delta),
+                        None => Some(0),
+                    }
+                }
+                // (a -- p1 ... pn), where n isn't known until a is factored, which is left to
+                // compute.
+                Factor => {
+                    delta.checked_sub(1)?;
+                    return Some(None);
+                }
+                // (a b c -- d)
+                BitExtract | InNetwork | PowerMod => delta.checked_sub(3).map(|d| d + 1),
+                // (a b c d -- e)
+                BitInsert => delta.checked_sub(4).map(|d| d + 1),
+                // (a b c -- b c a)
+                Rot => delta.checked_sub(3).map(|d| d + 3),
+                // (xu ... x0 u -- xu ... x0 xu)
+                Pick => {
+                    let depth = literal.map_or(1, |u: usize| u.saturating_add(1));
+                    delta.checked_sub(depth.saturating_add(1)).map(|_| delta)
+                }
+                // (xu ... x0 u -- xu-1 ... x0 xu)
+                Roll => {
+                    let depth = literal.map_or(1, |u: usize| u.saturating_add(1));
+                    delta
+                        .checked_sub(depth.saturating_add(1))
+                        .map(|_| delta - 1)
+                }
+                // (xn ... x1 n --), where n is only known ahead of time when it's a literal.
+                DropN => delta.checked_sub(literal.unwrap_or(0).saturating_add(1)),
+                // (xn ... x1 n -- xn ... x1 xn ... x1)
+                DupN => {
+                    let count = literal.unwrap_or(0);
+                    let delta = delta.checked_sub(1)?;
+                    delta
+                        .checked_sub(count)
+                        .map(|_| delta.saturating_add(count))
+                }
+                // (... xn ... x1 n -- xn ... x1), which keeps everything unless n is a literal.
+                Keep => {
+                    let delta = delta.checked_sub(1)?;
+                    match literal {
+                        Some(count) => delta.checked_sub(count).map(|_| count),
+                        None => Some(delta),
+                    }
+                }
+                // (a --)
+                Drop => delta.checked_sub(1),
+                Empty => Some(0),
+                // (... -- a)
+                Sum | Product => Some(1),
+                MinAll | MaxAll => delta.checked_sub(1).map(|_| 1),
+                // (... -- ...)
+                Reverse | Sort | ReverseSort => Some(delta),
+                Summary(statistic) => delta.checked_sub(statistic.min_count()).map(|_| 1),
+                // (xn ... x1 n -- a), where n is only known ahead of time when it's a literal.
+                SummaryOf(statistic) => {
+                    let count = literal.unwrap_or(statistic.min_count());
+                    delta.checked_sub(count.saturating_add(1)).map(|d| d + 1)
+                }
+                WordSize(_) | Precision(_) | MaxBits(_) | Seed(_) | Angles(_) | Radix(_)
+                | Style(_) | Group(_) | Visible(_) | Listing(_) | Trace(_) | Lenient(_)
+                | Results | Browse | SetKeymap(_) | Help(_) | Save(_) | Define | EndDefine | If
+                | Else | Then | Do | Loop => Some(delta),
+            }?;
+            literal = match token {
+                Number(n) => to_count(n),
+                _ => None,
+            };
+        }
+        Some(Some(delta))
+    }
+
+    /// Approximate `constant` to the current precision.
+    fn constant(&mut self, constant: Constant) -> Result<Rational, CalcError> {
+        let precision = self.settings.precision;
+        let (accuracy, approx) = match self.constants.get(&constant) {
+            Some((accuracy, approx)) if *accuracy >= precision => (*accuracy, approx.clone()),
+            _ => {
+                let approx = approx::constant(constant, precision, &self.interrupt)?;
+                self.constants.insert(constant, (precision, approx.clone()));
+                (precision, approx)
+            }
+        };
+        Ok(approx::simplify(&approx, accuracy, precision))
+    }
+
+    /// Pop the top of the stack, which must be a plain number.
+    fn pop(&mut self) -> Result<Rational, CalcError> {
+        self.pop_quantity()?.into_number()
+    }
+
+    /// Pop the top of the stack.
+    ///
+    /// The stack depth is checked before a line runs, but words and loops can leave it short,
+    /// so an empty stack fails the line rather than letting an operator go without an operand.
+    fn pop_quantity(&mut self) -> Result<Quantity, CalcError> {
+        self.stack.pop().ok_or(CalcError::StackUnderflow)
+    }
+
+    /// The top of the stack, for operators that leave it in place. As with `pop_quantity`, an
+    /// empty stack fails the line.
+    fn top(&self) -> Result<&Quantity, CalcError> {
+        self.stack.last().ok_or(CalcError::StackUnderflow)
+    }
+
+    /// Replace `entries`, which have been taken off of the stack, with their `statistic`.
+    fn summarize(&mut self, statistic: Statistic, entries: Vec<Quantity>) -> Result<(), CalcError> {
+        let values = entries
+            .into_iter()
+            .map(Quantity::into_number)
+            .collect::<Result<_, _>>()?;
+        let result = statistic.compute(values, self.settings.precision, &self.interrupt)?;
+        self.push(result);
+        Ok(())
+    }
+
+    /// The random number generator, seeding it if it hasn't been already.
+    fn rng(&mut self) -> &mut StdRng {
+        #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+        let seed = StdRng::from_entropy;
+        // Without an operating system to ask for entropy, start from a fixed seed. That includes
+        // wasm32, where `rand` has no source of entropy to ask.
+        #[cfg(any(not(feature = "std"), target_arch = "wasm32"))]
+        let seed = || StdRng::from_seed(Default::default());
+        self.rng.get_or_insert_with(seed)
+    }
+
+    /// Push a plain number.
+    fn push(&mut self, num: Rational) {
+        self.push_quantity(num.into());
+    }
+
+    /// Push a value computed by the current line, normalizing it.
+    ///
+    /// Entries are only normalized as they're made, so that a line doesn't pay for the entries
+    /// below the ones it touches.
+    fn push_quantity(&mut self, mut quantity: Quantity) {
+        Rc::make_mut(&mut quantity.value).normalize();
+        self.stack.push(quantity);
+    }
+
+    /// Find the index into the stack of the entry `count` places below the top.
+    fn stack_index(&self, count: &Rational) -> Result<usize, CalcError> {
+        match to_count(count) {
+            Some(count) if count < self.stack.len() => Ok(self.stack.len() - 1 - count),
+            Some(_) => Err(CalcError::StackIndexTooDeep),
+            None => Err(CalcError::InvalidStackIndex),
+        }
+    }
+
+    /// The index of the first of the top `count` entries, which must be on the stack.
+    fn count_start(&self, count: &Rational) -> Result<usize, CalcError> {
+        let count = to_count(count).ok_or(CalcError::InvalidStackIndex)?;
+        self.stack
+            .len()
+            .checked_sub(count)
+            .ok_or(CalcError::StackIndexTooDeep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A calculator that has evaluated `lines`, each of which must succeed.
+    fn run(lines: &[&str]) -> Calculator {
+        let mut calculator = Calculator::default();
+        for line in lines {
+            run_on(&mut calculator, line);
+        }
+        calculator
+    }
+
+    /// Evaluate `line`, which must succeed.
+    fn run_on(calculator: &mut Calculator, line: &str) {
+        if let Err(errors) = calculator.parse(line) {
+            panic!("`{}` failed: {:?}", line, errors);
+        }
+    }
+
+    /// The error that lexing the single word `word` stops at.
+    fn lex_error(word: &str) -> TokenError {
+        match Token::lex(word, Some(DEFAULT_MAX_BITS)).next() {
+            Some(Err(error)) => error,
+            other => panic!("`{}` lexed as {:?}", word, other),
+        }
+    }
+
+    /// The error that evaluating `line` stops at.
+    fn error(calculator: &mut Calculator, line: &str) -> CalcError {
+        match calculator.parse(line) {
+            Ok(()) => panic!("`{}` succeeded", line),
+            Err(mut errors) => errors.remove(0).error,
+        }
+    }
+
+    /// The stack as it is displayed, bottom first.
+    fn shown(calculator: &Calculator) -> Vec<String> {
+        let (formatter, settings) = (calculator.formatter(), calculator.settings());
+        calculator
+            .stack()
+            .iter()
+            .map(|entry| formatter.quantity(entry, settings))
+            .collect()
+    }
+
+    fn ratio(num: i64, den: i64) -> Rational {
+        Rational::new(Int::from(num), Int::from(den))
+    }
+
+    #[test]
+    fn scientific_notation() {
+        let calculator = run(&["6.02e23 1e-9 12.5e-1 0.125e2 1.5E3 1.5E+3 2.5e-3"]);
+        let big = Rational::from(602) * Rational::from(Int::from(10).pow(21));
+        let expected = [
+            big,
+            ratio(1, 1_000_000_000),
+            ratio(5, 4),
+            ratio(25, 2),
+            Rational::from(1500),
+            Rational::from(1500),
+            ratio(1, 400),
+        ];
+        assert_eq!(calculator.stack(), &expected);
+    }
+
+    #[test]
+    fn scientific_notation_with_units() {
+        let calculator = run(&["1.5e3m 2e-2km"]);
+        let shown: Vec<_> = calculator
+            .stack()
+            .iter()
+            .map(|entry| {
+                calculator
+                    .formatter()
+                    .quantity(entry, calculator.settings())
+            })
+            .collect();
+        assert_eq!(shown, ["1500 m", "1/50 km"]);
+    }
+
+    #[test]
+    fn malformed_exponents() {
+        for word in &["1e-", "1e+", "1.5e-x"] {
+            let error = lex_error(word);
+            assert!(
+                matches!(error.error, CalcError::InvalidExponent(_)),
+                "{}",
+                word
+            );
+            assert_eq!(error.span.start, word.find(['e', 'E']).unwrap(), "{}", word);
+        }
+        assert_eq!(lex_error("1e5x").error, CalcError::InvalidUnit);
+        assert_eq!(lex_error("1.5x3").error, CalcError::InvalidUnit);
+        assert_eq!(lex_error("1.5.e3").error, CalcError::InvalidFraction);
+    }
+
+    #[test]
+    fn huge_exponents() {
+        let huge = lex_error("1e99999999");
+        assert_eq!(huge.error, CalcError::ResultTooLarge(DEFAULT_MAX_BITS));
+        assert_eq!(huge.span, 1..10);
+        let mut calculator = run(&["maxbits 100"]);
+        assert_eq!(
+            error(&mut calculator, "1.5e-31"),
+            CalcError::ResultTooLarge(100)
+        );
+        assert_eq!(
+            error(&mut calculator, "=( 2 * 1e40 )"),
+            CalcError::ResultTooLarge(100)
+        );
+        run_on(&mut calculator, "1e30 maxbits unlimited");
+        run_on(&mut calculator, "1e1000");
+        assert_eq!(calculator.stack().len(), 2);
+    }
+
+    #[test]
+    fn malformed_exponents_leave_the_stack() {
+        let mut calculator = run(&["1"]);
+        assert!(calculator.parse("2 1e- +").is_err());
+        assert_eq!(calculator.stack(), &[Rational::from(1)]);
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut calculator = run(&["1 2 +", "4 *"]);
+        run_on(&mut calculator, "undo");
+        assert_eq!(calculator.stack(), &[Rational::from(3)]);
+        run_on(&mut calculator, "undo");
+        assert!(calculator.stack().is_empty());
+        assert_eq!(error(&mut calculator, "undo"), CalcError::NothingToUndo);
+        run_on(&mut calculator, "redo redo");
+        assert_eq!(calculator.stack(), &[Rational::from(12)]);
+        assert_eq!(error(&mut calculator, "redo"), CalcError::NothingToRedo);
+    }
+
+    #[test]
+    fn undo_skips_failed_lines() {
+        let mut calculator = run(&["1 2 +"]);
+        assert!(calculator.parse("5 0 /").is_err());
+        run_on(&mut calculator, "undo");
+        assert!(calculator.stack().is_empty());
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let saved = run(&["word 8 hex 3 =x drop 7 sto 2 drop : sq dup * ; 1.5m 4"]);
+        let mut restored = Calculator::default();
+        restored.load_session("saved", &saved.session()).unwrap();
+        assert_eq!(restored.stack(), saved.stack());
+        assert_eq!(restored.names().collect::<Vec<_>>(), ["sq", "x"]);
+        assert_eq!(restored.settings().word_size(), Some(8));
+        assert_eq!(restored.render(), saved.render());
+        run_on(&mut restored, "rcl 2 sq");
+        assert_eq!(restored.stack().last(), Some(&Rational::from(49).into()));
+    }
+
+    #[test]
+    fn invalid_session_changes_nothing() {
+        let mut calculator = run(&["1 2"]);
+        match calculator.load_session("bad", "3 4\n5 0 /") {
+            Err(CalcError::InvalidSession(message)) => assert!(message.starts_with("bad:2:")),
+            other => panic!("expected an invalid session, not {:?}", other),
+        }
+        assert_eq!(calculator.stack(), &[1, 2].map(Rational::from));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn loading_is_not_underflow() {
+        let mut calculator = Calculator::default();
+        match error(&mut calculator, "load /nonexistent/session +") {
+            CalcError::Io(_) => (),
+            other => panic!("expected an Io error, not {:?}", other),
+        }
+    }
+
+    #[test]
+    fn named_stacks() {
+        let mut calculator = run(&["1 2 stack work 3"]);
+        assert_eq!(calculator.stack_name(), "work");
+        assert_eq!(calculator.stack(), &[Rational::from(3)]);
+        run_on(&mut calculator, "stack main");
+        assert_eq!(calculator.stack(), &[1, 2].map(Rational::from));
+    }
+
+    #[test]
+    fn switching_stacks_is_not_underflow() {
+        let mut calculator = run(&["stack work 1 2 stack main"]);
+        run_on(&mut calculator, "stack work +");
+        assert_eq!(calculator.stack(), &[Rational::from(3)]);
+    }
+
+    #[test]
+    fn signed_only_for_negative() {
+        let calculator = run(&["word 8 hex 0 1 - 255 5"]);
+        let lines = calculator.render();
+        assert!(lines[0].ends_with("0xff (-1)"), "{:?}", lines);
+        assert!(lines[1].ends_with("0xff"), "{:?}", lines);
+        assert!(lines[2].ends_with("0x5"), "{:?}", lines);
+    }
+
+    #[test]
+    fn word_sizes() {
+        let mut calculator = run(&["word 16"]);
+        assert_eq!(calculator.settings().word_size(), Some(16));
+        for size in ["0", "4", "12", "128", "4294967295", "-8"] {
+            let line = format!("word {}", size);
+            assert_eq!(error(&mut calculator, &line), CalcError::InvalidWordSize);
+        }
+        run_on(&mut calculator, "word unlimited");
+        assert_eq!(calculator.settings().word_size(), None);
+    }
+
+    #[test]
+    fn too_big_for_the_word() {
+        let calculator = run(&["word 8 hex 1234567 0 1000 - 0 128 -"]);
+        assert_eq!(shown(&calculator), ["0x12d687", "-0x3e8", "0x80"]);
+        let lines = calculator.render();
+        assert_eq!(
+            lines.iter().filter(|line| line.contains('(')).count(),
+            1,
+            "{:?}",
+            lines
+        );
+    }
+
+    #[test]
+    fn signed_in_binary() {
+        let calculator = run(&["word 8 bin 0 3 -"]);
+        assert!(calculator.render()[0].ends_with("0b11111101 (-3)"));
+    }
+
+    #[test]
+    fn failed_line_rolls_back() {
+        let mut calculator = run(&["1 2 =x"]);
+        assert!(calculator.parse("3 4 + =y 5 0 /").is_err());
+        assert_eq!(calculator.stack(), &[1, 2].map(Rational::from));
+        assert_eq!(calculator.names().collect::<Vec<_>>(), ["x"]);
+        assert!(calculator.parse(": sq dup * ; 7 sto 1 hex 1 0 /").is_err());
+        assert_eq!(calculator.names().collect::<Vec<_>>(), ["x"]);
+        assert_eq!(calculator.formatter().base, Base::Decimal);
+        run_on(&mut calculator, "rcl 1");
+        assert_eq!(calculator.stack().last(), Some(&Rational::zero().into()));
+    }
+
+    #[test]
+    fn underflow() {
+        let mut calculator = run(&["1"]);
+        for line in &[
+            "swap",
+            "over",
+            "2 rot",
+            "+",
+            "drop drop",
+            "nip",
+            "drop bits",
+            "drop sto 1",
+        ] {
+            assert_eq!(error(&mut calculator, line), CalcError::StackUnderflow);
+            assert_eq!(calculator.stack(), &[Rational::from(1)]);
+        }
+    }
+
+    #[test]
+    fn continued_lines() {
+        assert!(Token::continues("1 2 \\", None));
+        assert!(Token::continues("1 2 \\  ", None));
+        assert!(Token::continues(": sq dup", None));
+        assert!(!Token::continues(": sq dup * ;", None));
+        assert!(!Token::continues("1 2 +", None));
+    }
+
+    #[test]
+    fn failed_line_rolls_back_modes() {
+        let mut calculator = Calculator::default();
+        assert!(calculator.parse("trace on 1 0 /").is_err());
+        // The trace of the failed line is kept to show where it went wrong.
+        assert!(!calculator.take_output().is_empty());
+        run_on(&mut calculator, "1 2 +");
+        assert!(calculator.take_output().is_empty());
+
+        assert!(calculator.parse("help sqrt 1 0 /").is_err());
+        assert!(calculator.take_output().is_empty());
+        assert!(calculator.parse("browse 1 0 /").is_err());
+        assert!(!calculator.take_browse());
+        assert!(calculator.parse("set keymap vi 1 0 /").is_err());
+        assert_eq!(calculator.take_keymap(), None);
+    }
+
+    #[test]
+    fn failed_line_rolls_back_seed() {
+        let mut failed = run(&["seed 5 rand"]);
+        assert!(failed.parse("seed 6 1 0 /").is_err());
+        run_on(&mut failed, "rand");
+        let kept = run(&["seed 5 rand", "rand"]);
+        assert_eq!(failed.stack(), kept.stack());
+    }
+
+    #[test]
+    fn trace() {
+        let mut calculator = run(&["trace on"]);
+        calculator.take_output();
+        run_on(&mut calculator, "1 2 +");
+        assert_eq!(calculator.take_output().len(), 3);
+    }
+
+    #[test]
+    fn lenient_skips_unreadable_tokens() {
+        let mut calculator = run(&["lenient on", "1 foo 2 +"]);
+        assert_eq!(calculator.stack(), &[Rational::from(3)]);
+        assert_eq!(calculator.take_output().len(), 1);
+        run_on(&mut calculator, "lenient off");
+        assert_eq!(
+            error(&mut calculator, "1 foo 2 +"),
+            CalcError::Undefined("foo".to_string())
+        );
+    }
+
+    #[test]
+    fn past_results() {
+        let mut calculator = run(&["255", "1 2 +", "hex", "$2 $1 +"]);
+        assert_eq!(calculator.stack(), &[255, 3, 258].map(Rational::from));
+        assert_eq!(error(&mut calculator, "$9"), CalcError::NoResult(9));
+    }
+
+    #[test]
+    fn counted_drop_and_dup() {
+        let calculator = run(&["1 2 3 2 dropn"]);
+        assert_eq!(calculator.stack(), &[Rational::from(1)]);
+        let calculator = run(&["1 2 2 dupn"]);
+        assert_eq!(calculator.stack(), &[1, 2, 1, 2].map(Rational::from));
+        let mut calculator = run(&["1 2"]);
+        assert_eq!(error(&mut calculator, "3 dupn"), CalcError::StackUnderflow);
+        assert_eq!(
+            error(&mut calculator, "1 2 / dropn"),
+            CalcError::InvalidStackIndex
+        );
+        assert_eq!(calculator.stack(), &[1, 2].map(Rational::from));
+    }
+
+    #[test]
+    fn nip_and_tuck() {
+        let calculator = run(&["1 2 nip"]);
+        assert_eq!(calculator.stack(), &[Rational::from(2)]);
+        let calculator = run(&["1 2 tuck"]);
+        assert_eq!(calculator.stack(), &[2, 1, 2].map(Rational::from));
+    }
+
+    #[test]
+    fn reverse_and_sort() {
+        let calculator = run(&["3 1 2 rev"]);
+        assert_eq!(calculator.stack(), &[2, 1, 3].map(Rational::from));
+        let calculator = run(&["3 1 2 sort"]);
+        assert_eq!(calculator.stack(), &[1, 2, 3].map(Rational::from));
+        let calculator = run(&["3 1 2 rsort"]);
+        assert_eq!(calculator.stack(), &[3, 2, 1].map(Rational::from));
+        let calculator = run(&["2m 300cm 1m sort"]);
+        assert_eq!(shown(&calculator), ["1 m", "2 m", "300 cm"]);
+    }
+
+    #[test]
+    fn sort_incompatible_units() {
+        let mut calculator = run(&["1m 2s"]);
+        assert_eq!(error(&mut calculator, "sort"), CalcError::IncompatibleUnits);
+        assert_eq!(
+            error(&mut calculator, "rsort"),
+            CalcError::IncompatibleUnits
+        );
+        assert_eq!(shown(&calculator), ["1 m", "2 s"]);
+    }
+
+    #[test]
+    fn keep() {
+        let calculator = run(&["1 2 3 2 keep"]);
+        assert_eq!(calculator.stack(), &[2, 3].map(Rational::from));
+        let calculator = run(&["1 2 0 keep"]);
+        assert!(calculator.stack().is_empty());
+        let mut calculator = run(&["1 2"]);
+        assert_eq!(error(&mut calculator, "3 keep"), CalcError::StackUnderflow);
+        assert_eq!(calculator.stack(), &[1, 2].map(Rational::from));
+    }
+
+    #[test]
+    fn unit_powers() {
+        assert_eq!(lex_error("1km^2000000000").error, CalcError::InvalidUnit);
+        assert_eq!(lex_error("1m^-2147483648").error, CalcError::InvalidUnit);
+        let mut calculator = run(&["1km^100 1m^-100 *"]);
+        let power = Rational::from(Int::from(10).pow(300));
+        assert_eq!(calculator.stack(), &[power]);
+        for line in &[
+            "1m^100 1m *",
+            "1m^-100 1m /",
+            "1m^60 2 ^",
+            "1m 2147483647 ^",
+        ] {
+            assert_eq!(error(&mut calculator, line), CalcError::UnitPowerTooLarge);
+        }
+        run_on(&mut calculator, "maxbits 100");
+        assert_eq!(
+            error(&mut calculator, "1km^20"),
+            CalcError::ResultTooLarge(100)
+        );
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let calculator = run(&["1 if 1 if 2 do 3 loop else 4 then then"]);
+        assert_eq!(calculator.stack(), &[Rational::from(3), Rational::from(3)]);
+        let line = "1 if ".repeat(1000);
+        let errors = Calculator::default().parse(&line).unwrap_err();
+        let start = MAX_BLOCK_DEPTH * "1 if ".len() + "1 ".len();
+        assert_eq!(errors[0].error, CalcError::NestedTooDeep);
+        assert_eq!(errors[0].span, start..start + "if".len());
+        let line = "1 do ".repeat(1000);
+        let errors = Calculator::default().parse(&line).unwrap_err();
+        assert_eq!(errors[0].error, CalcError::NestedTooDeep);
+    }
+
+    #[test]
+    fn infix() {
+        let calculator = run(&[
+            "=( 1 + 2 * 3 ) =( (1 + 2) * 3 ) =( 2 ^ 3 ^ 2 ) =( -2^2 ) =( 10 - 4 - 3 )",
+            "=( max(gcd(12, 18), 2 * 2) ) =( abs(-3) + 1 )",
+        ]);
+        let expected: Vec<Rational> = [7, 9, 512, -4, 3, 6, 4]
+            .iter()
+            .map(|&n| Rational::from(n))
+            .collect();
+        assert_eq!(calculator.stack(), &expected[..]);
+    }
+
+    #[test]
+    fn infix_errors() {
+        for (line, expected, span) in [
+            ("=( 1 + )", CalcError::ExpectedOperand, 7..8),
+            ("=( 1 2 )", CalcError::ExpectedOperator, 5..6),
+            ("=( max(1, 2 )", CalcError::UnbalancedParens, 0..13),
+            (
+                "=( 2 * 1e99999999 )",
+                CalcError::ResultTooLarge(DEFAULT_MAX_BITS),
+                8..17,
+            ),
+        ] {
+            let errors = Calculator::default().parse(line).unwrap_err();
+            assert_eq!(
+                (&errors[0].error, &errors[0].span),
+                (&expected, &span),
+                "{}",
+                line
+            );
+        }
+        // The expression that goes too deep is nested within 256 others.
+        for (unit, count) in [("(", 20_000), ("-", 100_000), ("2^", 1000)] {
+            let close = if unit == "(" { ")" } else { "" };
+            let line = format!("=( {}1{} )", unit.repeat(count), close.repeat(count));
+            let errors = Calculator::default().parse(&line).unwrap_err();
+            let start = "=( ".len() + 256 * unit.len();
+            assert_eq!(errors[0].error, CalcError::ExpressionTooDeep);
+            assert_eq!(errors[0].span, start..start + 1);
+        }
+    }
+
+    #[test]
+    fn factor() {
+        let mut calculator = run(&["360 factor * * * * *"]);
+        assert_eq!(calculator.stack(), &[Rational::from(360)]);
+        assert_eq!(
+            error(&mut calculator, "drop 7 factor +"),
+            CalcError::StackUnderflow
+        );
+        assert_eq!(calculator.stack(), &[Rational::from(360)]);
+        assert_eq!(
+            error(&mut calculator, "drop factor"),
+            CalcError::StackUnderflow
+        );
+    }
+
+    #[test]
+    fn large_powers() {
+        let mut calculator = run(&["maxbits 64", "1 1000000000 ^ 1 neg 1000000001 ^ 0 99999 ^"]);
+        assert_eq!(
+            calculator.stack(),
+            &[Rational::from(1), Rational::from(-1), Rational::from(0)]
+        );
+        assert_eq!(
+            error(&mut calculator, "3 41 ^"),
+            CalcError::ResultTooLarge(64)
+        );
+        let mut calculator = run(&["maxbits 1000"]);
+        let limit = CalcError::ResultTooLarge(1000);
+        assert_eq!(error(&mut calculator, "2 500 ^ 1000 perm"), limit);
+        assert_eq!(error(&mut calculator, "2 500 ^ 1000 choose"), limit);
+        assert_eq!(error(&mut calculator, "2 500 ^ dup dup dup prod"), limit);
+        assert_eq!(error(&mut calculator, "200 fact"), limit);
+        assert!(calculator.stack().is_empty());
+        run_on(
+            &mut calculator,
+            "2 400 ^ dup prod 2 400 ^ 2 perm 2 400 ^ 2 choose",
+        );
+        assert_eq!(calculator.stack().len(), 3);
+    }
+
+    #[test]
+    fn roots() {
+        let mut calculator = run(&["27 3 root 4 9 / sqrt 2 100 root"]);
+        assert_eq!(calculator.stack()[..2], [Rational::from(3), ratio(2, 3)]);
+        let limit = CalcError::ResultTooLarge(DEFAULT_MAX_BITS);
+        assert_eq!(error(&mut calculator, "2 1000000 root"), limit);
+        assert_eq!(error(&mut calculator, "prec 10000000 2 sqrt"), limit);
+        assert_eq!(calculator.settings().precision(), approx::DEFAULT_PRECISION);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn interrupt() {
+        let mut calculator = run(&["1"]);
+        let handle = calculator.interrupt_handle();
+        // An interrupt only stops the line that is running when it comes.
+        handle.interrupt();
+        run_on(&mut calculator, "2");
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            handle.interrupt();
+        });
+        assert_eq!(
+            error(&mut calculator, "3 prec 1000000 pi"),
+            CalcError::Interrupted
+        );
+        interrupter.join().unwrap();
+        assert_eq!(calculator.stack(), &[Rational::from(1), Rational::from(2)]);
+        // Each calculator has its own flag.
+        Calculator::default().interrupt_handle().interrupt();
+        run_on(&mut calculator, "3");
+    }
+}
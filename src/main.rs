@@ -1,362 +1,492 @@
-use core::ops::Range;
-use liner::{Completer, Context};
-use num_traits::{One, Zero};
-use ramp::{rational::Rational, Int};
-use std::fmt::Display;
-use std::str::FromStr;
-use std::io::{stdin, BufRead};
-use termion::{color, is_tty};
-use Token::*;
+use config::Config;
+use liner::{Completer, Context, History, KeyBindings};
+use rcalc::{json, Calculator, Keymap, Token, TokenError};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+use termion::color::{self, AnsiValue};
+use termion::is_tty;
 
-// Readable tokens from command line
-#[derive(Debug, PartialEq, Clone)]
-pub enum Token {
-    Number(Rational),
-    Minus,
-    Plus,
-    Times,
-    Divide,
-    Exp,
-    And,
-    Or,
-    Duplicate,
-    Drop,
-    Empty,
-    Round,
+mod browse;
+mod config;
+
+/// Completes operator words, user defined words and variable names.
+struct WordCompleter {
+    words: Vec<String>,
 }
 
-pub struct TokenError {
-    pub message: Box<dyn Display>,
-    pub span: Range<usize>,
+impl WordCompleter {
+    fn new(calculator: &Calculator) -> Self {
+        let mut words: Vec<String> = Token::words()
+            .map(String::from)
+            .chain(calculator.names().map(String::from))
+            .collect();
+        words.sort();
+        words.dedup();
+        WordCompleter { words }
+    }
 }
 
-fn unexpected_trailing_chars(
-    from: &'_ str,
-    token: Token,
-    size: usize,
-) -> Result<Token, TokenError> {
-    if from.len() == size {
-        Ok(token)
-    } else {
-        Err(TokenError {
-            message: Box::new("Unexpected trailing characters"),
-            span: size..(from.len()),
-        })
+impl Completer for WordCompleter {
+    fn completions(&mut self, start: &str) -> Vec<String> {
+        // `=name` stores to a variable, so complete the name after the `=`.
+        let (prefix, start) = match start.strip_prefix('=') {
+            Some(name) => ("=", name),
+            None => ("", start),
+        };
+        self.words
+            .iter()
+            .filter(|word| word.starts_with(start))
+            .map(|word| format!("{}{}", prefix, word))
+            .collect()
     }
 }
 
-impl FromStr for Token {
-    type Err = TokenError;
-    fn from_str(from: &'_ str) -> Result<Self, Self::Err> {
-        let mut chars = from.chars();
-        match chars.next().ok_or(TokenError {
-            message: Box::new("unexpected empty token"),
-            span: 0..0,
-        })? {
-            '%' => unexpected_trailing_chars(from, Empty, 1),
-            '!' => unexpected_trailing_chars(from, Drop, 1),
-            '<' => unexpected_trailing_chars(from, Duplicate, 1),
-            '^' => unexpected_trailing_chars(from, Exp, 1),
-            '/' => unexpected_trailing_chars(from, Divide, 1),
-            '*' => unexpected_trailing_chars(from, Times, 1),
-            '+' => unexpected_trailing_chars(from, Plus, 1),
-            '-' => unexpected_trailing_chars(from, Minus, 1),
-            '|' => unexpected_trailing_chars(from, Or, 1),
-            '&' => unexpected_trailing_chars(from, And, 1),
-            '_' => unexpected_trailing_chars(from, Round, 1),
-            '0' => match chars.next() {
-                Some('x') => match Int::from_str_radix(&from[2..], 16) {
-                    Ok(n) => Ok(Number(n.into())),
-                    Err(e) => Err(TokenError {
-                        message: Box::new(e),
-                        span: 2..from.len(),
-                    }),
-                },
-                Some('b') => match Int::from_str_radix(&from[2..], 2) {
-                    Ok(n) => Ok(Number(n.into())),
-                    Err(e) => Err(TokenError {
-                        message: Box::new(e),
-                        span: 2..from.len(),
-                    }),
-                },
-                _ => match Int::from_str_radix(from, 10) {
-                    Ok(n) => Ok(Number(n.into())),
-                    Err(e) => Err(TokenError {
-                        message: Box::new(e),
-                        span: 0..from.len(),
-                    }),
-                },
+/// Wrap `text` in the escape codes for `color`, or leave it alone if there is no color.
+fn paint(text: &str, color: Option<AnsiValue>) -> String {
+    match color {
+        Some(color) => format!("{}{}{}", color::Fg(color), text, color::Fg(color::Reset)),
+        None => text.to_string(),
+    }
+}
+
+/// Colorize errors with `error_color`, where literals with more than `max_bits` bits are errors
+fn colorize(word: &str, error_color: Option<AnsiValue>, max_bits: Option<usize>) -> String {
+    let mut res = String::with_capacity(word.len());
+    let mut last = 0;
+    for token in Token::lex(word, max_bits) {
+        if let Err(te) = token {
+            res.push_str(&word[last..te.span.start]);
+            res.push_str(&paint(&word[te.span.clone()], error_color));
+            last = te.span.end;
+        }
+    }
+    res.push_str(&word[last..]);
+    res
+}
+
+const USAGE: &str =
+    "usage: rcalc [-i] [--no-color] [--no-limit] [--lenient] [--history-size LINES] [--output text|json] [-e EXPRESSION]... [SCRIPT]";
+
+/// The number of lines of history kept when `--history-size` isn't given.
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// How results are written when input isn't coming from a terminal.
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    /// The stack as it is displayed interactively, printed once the input ends.
+    Text,
+    /// The stack after each line, as JSON.
+    Json,
+}
+
+/// Options from the command line.
+struct Args {
+    /// Expressions given with `-e`, evaluated in order instead of reading input.
+    expressions: Vec<String>,
+    /// A file of expressions to evaluate line by line.
+    script: Option<String>,
+    /// Keep reading input after the expressions and script instead of exiting.
+    interactive: bool,
+    /// The number of lines of history to keep between sessions.
+    history_size: usize,
+    /// Leave the colors out of the prompt and errors.
+    no_color: bool,
+    /// Let results grow without a limit on their bits.
+    no_limit: bool,
+    /// Skip tokens that can't be read and undefined names rather than failing the line.
+    lenient: bool,
+    output: Output,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut parsed = Args {
+        expressions: Vec::new(),
+        script: None,
+        interactive: false,
+        history_size: DEFAULT_HISTORY_SIZE,
+        // See https://no-color.org/
+        no_color: env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()),
+        no_limit: false,
+        lenient: false,
+        output: Output::Text,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--eval" => match args.next() {
+                Some(expression) => parsed.expressions.push(expression),
+                None => return Err(format!("`{}` requires an expression", arg)),
+            },
+            "-i" | "--interactive" => parsed.interactive = true,
+            "--no-color" => parsed.no_color = true,
+            "--no-limit" => parsed.no_limit = true,
+            "--lenient" => parsed.lenient = true,
+            "--history-size" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) => parsed.history_size = size,
+                _ => return Err(format!("`{}` requires a number of lines", arg)),
             },
-            c if c.is_ascii_digit() => match Int::from_str_radix(from, 10) {
-                Ok(n) => Ok(Number(n.into())),
-                Err(e) => Err(TokenError {
-                    message: Box::new(e),
-                    span: 0..from.len(),
-                }),
+            "--output" => match args.next().as_deref() {
+                Some("text") => parsed.output = Output::Text,
+                Some("json") => parsed.output = Output::Json,
+                _ => return Err(format!("`{}` requires `text` or `json`", arg)),
             },
-            _ => Err(TokenError {
-                message: Box::new("unexpected token"),
-                span: 0..from.len(),
-            }),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unexpected argument `{}`", arg)),
+            _ if parsed.script.is_none() => parsed.script = Some(arg),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
         }
     }
+    Ok(parsed)
 }
 
-fn subslice_offset(slice: &str, sub: &str) -> Option<usize> {
-    let self_begin = slice.as_ptr() as usize;
-    let inner = sub.as_ptr() as usize;
-    if inner < self_begin || inner > self_begin.wrapping_add(slice.len()) {
-        None
-    } else {
-        Some(inner.wrapping_sub(self_begin))
-    }
+/// Print an error without colors, underlining the span of `input` that caused it.
+fn print_error(label: &str, input: &str, error: &TokenError) {
+    eprintln!(
+        "{}: {}\n{}{} {}",
+        label,
+        input,
+        " ".repeat(error.span.start + label.len() + 2),
+        "^".repeat(error.span.len()),
+        error,
+    );
+}
+
+/// The path of `file` in rcalc's directory under the XDG base directory `var`, which is
+/// `fallback` in the home directory when unset.
+fn xdg_path(var: &str, fallback: &str, file: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(fallback)))?;
+    Some(base.join("rcalc").join(file))
+}
+
+/// Where history is saved between sessions.
+fn history_path() -> Option<PathBuf> {
+    xdg_path("XDG_DATA_HOME", ".local/share", "history")
+}
+
+/// Where the REPL keeps the session between runs, with `persist = true`.
+fn state_path() -> Option<PathBuf> {
+    xdg_path("XDG_DATA_HOME", ".local/share", "state")
 }
 
-impl Token {
-    pub fn lex(from: &'_ str) -> impl Iterator<Item = Result<Token, <Token as FromStr>::Err>> + '_ {
-        from.split_whitespace().map(move |s| {
-            // Note: This is a safe unwrap, as the subslice_offset function only returns
-            // None when s is not a subslice of from. This can't happen.
-            let offset = subslice_offset(from, s).unwrap();
-            Token::from_str(s).map_err(|e| TokenError {
-                span: (e.span.start + offset)..(e.span.end + offset),
-                ..e
-            })
-        })
+/// Restore the session that the last REPL saved to `path`, if there is one.
+fn load_state(calculator: &mut Calculator, path: &Path) -> Result<(), String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    calculator
+        .load_session(&path.display().to_string(), &text)
+        .map_err(|e| e.to_string())
+}
+
+/// Save the session to `path`, for the next REPL to restore.
+fn save_state(calculator: &Calculator, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
     }
+    fs::write(path, calculator.session())
 }
 
-#[derive(Default)]
-pub struct Calculator {
-    stack: Vec<Rational>,
+/// Where the configuration file is read from.
+fn config_path() -> Option<PathBuf> {
+    xdg_path("XDG_CONFIG_HOME", ".config", "config.toml")
 }
 
-impl Calculator {
-    // Parse a line into tokens and compute them
-    pub fn parse(&mut self, word: &str) -> Result<(), TokenError> {
-        let tokens = Token::lex(word).collect::<Result<Vec<_>, _>>()?;
-        // We check for stack exhaustion before attempting to run anything.
-        // that way we don't end up with a half-evaluated expression.
-        self.check_stack_exhaustion(&tokens)
-            .map_err(|message| TokenError {
-                message,
-                span: 0..word.len(),
-            })?;
-        self.compute(tokens).map_err(|message| TokenError {
-            message,
-            span: 0..word.len(),
-        })?;
-        for num in &mut self.stack {
-            num.normalize();
-        }
-        Ok(())
+/// Load history from `path`, creating it if this is the first session.
+fn load_history(history: &mut History, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
     }
+    history.set_file_name_and_load_history(path)?;
+    Ok(())
+}
 
-    fn compute(&mut self, tokens: impl IntoIterator<Item = Token>) -> Result<(), Box<dyn Display>> {
-        for token in tokens.into_iter() {
-            match token {
-                Duplicate => {
-                    if let Some(mut num) = self.stack.pop() {
-                        num.normalize();
-                        self.stack.push(num.clone());
-                        self.stack.push(num);
-                    } else {
-                        return Err(Box::new("Incomplete expression, dropped stack"));
-                    }
-                }
-                Empty => self.stack.clear(),
-                Drop => {
-                    self.stack.pop();
-                }
-                Number(n) => self.stack.push(n),
-                Plus => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(lhs + rhs);
-                    }
-                }
-                Minus => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(lhs - rhs);
-                    }
-                }
-                Times => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(lhs * rhs);
-                    }
-                }
-                Divide => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(if rhs.is_zero() {
-                            Rational::new(0.into(), 1.into())
-                        } else {
-                            lhs / rhs
-                        });
-                    }
-                }
-                Exp => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(lhs / rhs);
-                    }
-                }
-                And => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(Rational::new(lhs.round() & rhs.round(), 1.into()));
-                    }
-                }
-                Or => {
-                    let rhs = self.stack.pop();
-                    let lhs = self.stack.pop();
-                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
-                        self.stack.push(Rational::new(lhs.round() | rhs.round(), 1.into()));
-                    }
-                }
-                Round => {
-                    let rhs = self.stack.pop();
-                    if let Some(rhs) = rhs {
-                        self.stack.push(Rational::new(rhs.round(), 1.into()));
-                    }
-                }
+/// Evaluate `line` and write its results in the `output` format, exiting if it fails.
+///
+/// `label` names the line in error messages.
+fn evaluate(
+    calculator: &mut Calculator,
+    output: Output,
+    line: &str,
+    label: impl Fn(&TokenError) -> String,
+) {
+    // Output may be going to a file or another program, so leave out the colors.
+    if let Err(errors) = calculator.parse(line) {
+        for error in errors {
+            match output {
+                Output::Text => print_error(&label(&error), line, &error),
+                Output::Json => println!("{}", json::error(&label(&error), line, &error)),
+            }
+        }
+        process::exit(1);
+    }
+    match output {
+        Output::Text => print_output(calculator),
+        Output::Json => {
+            for text in calculator.take_output() {
+                println!("{}", json::string(&text));
             }
+            println!("{}", json::stack(calculator.stack()));
         }
-        Ok(())
     }
+}
 
-    fn check_stack_exhaustion<'a>(
-        &self,
-        stack: impl IntoIterator<Item = &'a Token>,
-    ) -> Result<(), Box<dyn Display>> {
-        let delta = stack
-            .into_iter()
-            .fold(Some(self.stack.len()), |delta, token| match token {
-                // ( -- a)
-                Number(_) | Duplicate => delta.map(|d| d + 1),
-                // This weirdness, the subtract and then add, is an exact representation of the stack affects
-                // of all of these operators, (a b -- c). We must be able to pop 2 off the stack, but we later
-                // add 1 back.
-                Plus | Minus | Times | Divide | Exp | Or | And => {
-                    delta.and_then(|d| d.checked_sub(2)).map(|d| d + 1)
-                }
-                Round => {
-                    delta.and_then(|d| d.checked_sub(1)).map(|d| d + 1)
-                }
-                // (a --)
-                Drop => delta.and_then(|d| d.checked_sub(1)),
-                Empty => Some(0),
+/// Evaluate each line of `input`, exiting on the first error.
+///
+/// `label` names the line in error messages, given its 0-based offset and the error.
+fn run_lines(
+    calculator: &mut Calculator,
+    output: Output,
+    input: impl BufRead,
+    label: impl Fn(usize, &TokenError) -> String,
+) {
+    // A continued line is labelled with the offset of its first line.
+    let mut pending: Option<(usize, String)> = None;
+    for (line_offset, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("unexpected IO error {}", e);
+                process::exit(2);
+            }
+        };
+        let (first_offset, line) = match pending.take() {
+            Some((first_offset, mut pending)) => {
+                join(&mut pending, &line);
+                (first_offset, pending)
+            }
+            None => (line_offset, line),
+        };
+        if Token::continues(&line, calculator.settings().max_bits()) {
+            pending = Some((first_offset, line));
+        } else {
+            evaluate(calculator, output, &line, |error| {
+                label(first_offset, error)
             });
-        match delta {
-            Some(_) => Ok(()),
-            None => Err(Box::new(
-                "Stack exhaustion would have occured during evaluation; aborting",
-            )),
         }
     }
+    // Input that ends partway through a line is evaluated as it is, to report what's missing.
+    if let Some((first_offset, line)) = pending {
+        evaluate(calculator, output, &line, |error| {
+            label(first_offset, error)
+        });
+    }
 }
 
-/// This completer does nothing.
-///
-/// Completion is not really helpful when you have no variables and
-/// all tokens are nearly 1 character.
-struct EmptyCompleter;
+/// Add `next` to the end of the continued `line`, leaving out the `\` that continued it.
+fn join(line: &mut String, next: &str) {
+    let trimmed = line.trim_end();
+    let len = trimmed.strip_suffix('\\').unwrap_or(trimmed).len();
+    line.truncate(len);
+    line.push(' ');
+    line.push_str(next);
+}
 
-impl Completer for EmptyCompleter {
-    fn completions(&mut self, _start: &str) -> Vec<String> {
-        Vec::new()
+/// Print the text from commands like `help`.
+fn print_output(calculator: &mut Calculator) {
+    for text in calculator.take_output() {
+        println!("{}", text);
     }
 }
 
-/// Colorize errors red
-fn colorize(word: &str) -> String {
-    let mut res = String::with_capacity(word.len());
-    let mut last = 0;
-    for token in Token::lex(&word) {
-        if let Err(te) = token {
-            res.push_str(&word[last..te.span.start]);
-            res.push_str(color::LightRed.fg_str());
-            res.push_str(&word[te.span.clone()]);
-            res.push_str(color::Reset.fg_str());
-            last = te.span.end;
-        }
+/// Switch the line editor to the key bindings from the last `set keymap`, if there was one.
+fn apply_keymap(con: &mut Context, calculator: &mut Calculator) {
+    con.key_bindings = match calculator.take_keymap() {
+        Some(Keymap::Vi) => KeyBindings::Vi,
+        Some(Keymap::Emacs) => KeyBindings::Emacs,
+        None => return,
+    };
+}
+
+/// Print the entries of the stack selected by `show`.
+fn print_stack(calculator: &Calculator) {
+    for line in calculator.render() {
+        println!("{}", line);
     }
-    res.push_str(&word[last..]);
-    res
 }
 
 fn main() -> std::io::Result<()> {
+    let args = parse_args().unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(2);
+    });
+    let mut config = match config_path() {
+        Some(path) => Config::load(&path).unwrap_or_else(|message| {
+            eprintln!("invalid configuration: {}", message);
+            process::exit(2);
+        }),
+        None => Config::default(),
+    };
+    if args.no_limit {
+        config.startup.push("maxbits unlimited".to_string());
+    }
+    if args.lenient {
+        config.startup.push("lenient on".to_string());
+    }
+    let batch = (!args.expressions.is_empty() || args.script.is_some()) && !args.interactive;
+    let repl = !batch && is_tty(&stdin());
     let mut calculator = Calculator::default();
-    if is_tty(&stdin()) {
+    // The saved session goes first, so that the configuration and arguments still apply on top.
+    let state = if config.persist && repl {
+        state_path()
+    } else {
+        None
+    };
+    if let Some(path) = &state {
+        if let Err(e) = load_state(&mut calculator, path) {
+            eprintln!(
+                "could not restore the session from {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    for (index, command) in config.startup.iter().enumerate() {
+        if let Err(errors) = calculator.parse(command) {
+            for error in errors {
+                print_error(&format!("startup {}", index + 1), command, &error);
+            }
+            process::exit(2);
+        }
+        print_output(&mut calculator);
+    }
+    for (index, expression) in args.expressions.iter().enumerate() {
+        evaluate(&mut calculator, args.output, expression, |_| {
+            format!("-e {}", index + 1)
+        });
+    }
+    if let Some(path) = &args.script {
+        let file = File::open(path).unwrap_or_else(|e| {
+            eprintln!("could not open {}: {}", path, e);
+            process::exit(2);
+        });
+        run_lines(
+            &mut calculator,
+            args.output,
+            BufReader::new(file),
+            |line_offset, error| format!("{}:{}:{}", path, line_offset + 1, error.span.start + 1),
+        );
+    }
+    if batch {
+        if args.output == Output::Text {
+            print_stack(&calculator);
+        }
+    } else if repl {
         let mut con = Context::new();
-        let prefix = color::Fg(color::Magenta);
-        let suffix = color::Fg(color::Reset);
-        let prompt = format!("{prefix}>>{suffix} ", prefix = prefix, suffix = suffix);
-        while let Ok(input) = con.read_line(&prompt, Some(Box::new(colorize)), &mut EmptyCompleter) {
-            match calculator.parse(&input) {
-                Ok(()) => (),
-                Err(TokenError { message, span }) => eprintln!(
-                    "{}{}{} {}{}",
-                    " ".repeat(span.start + 3),
-                    color::LightRed.fg_str(),
-                    "^".repeat(span.len()),
-                    message,
-                    color::Reset.fg_str(),
-                ),
+        con.history.set_max_buffers_size(args.history_size);
+        con.history.set_max_file_size(args.history_size);
+        // Append each line to the history file as it's entered, and reload the file before
+        // searching with Ctrl-R, so that searches find lines from other running sessions too.
+        con.history.inc_append = true;
+        con.history.share = true;
+        // From the configuration, or the last startup line that set one.
+        apply_keymap(&mut con, &mut calculator);
+        let history = history_path();
+        if let Some(path) = &history {
+            if let Err(e) = load_history(&mut con.history, path) {
+                eprintln!("could not load history from {}: {}", path.display(), e);
             }
-            for num in &calculator.stack {
-                let (num, den) = num.clone().into_parts();
-                if den.is_one() {
-                    println!("{num} (0x{num:x})", num = num);
-                } else {
-                    println!("{num}/{den} (0x{num:x}/{den:x})", num = num, den = den,);
+        }
+        let (prompt_color, error_color) = if args.no_color {
+            (None, None)
+        } else {
+            (config.prompt_color, config.error_color)
+        };
+        // Ctrl-C interrupts the line being evaluated, so that it stops a long computation
+        // without quitting. Evaluation stays on this thread, as entries share their values
+        // through `Rc`, so the handler only sets the calculator's interrupt flag, which
+        // evaluation checks as it goes. While a line is being read, the terminal is in raw mode
+        // and Ctrl-C is a key press instead.
+        let interrupt = calculator.interrupt_handle();
+        if let Err(e) = ctrlc::set_handler(move || interrupt.interrupt()) {
+            eprintln!("could not handle Ctrl-C: {}", e);
+        }
+        'lines: loop {
+            // The prompts show the state after the last line, so they're filled in each time.
+            let prompt = config::expand(&config.prompt, &calculator);
+            let continuation_prompt = config::expand(&config.continuation_prompt, &calculator);
+            let (indent, prompt) = (prompt.chars().count(), paint(&prompt, prompt_color));
+            let continuation_prompt = paint(&continuation_prompt, prompt_color);
+            let max_bits = calculator.settings().max_bits();
+            let highlight = move |word: &str| colorize(word, error_color, max_bits);
+            let mut completer = WordCompleter::new(&calculator);
+            let input = match con.read_line(&prompt, Some(Box::new(highlight)), &mut completer) {
+                Ok(input) => input,
+                Err(_) => break,
+            };
+            // liner continues lines that end with `\` itself, keeping the `\` and line break.
+            let continued = input.contains("\\\n") || Token::continues(&input, max_bits);
+            let mut input = input.replace("\\\n", " ");
+            while Token::continues(&input, max_bits) {
+                let next = con.read_line(
+                    &continuation_prompt,
+                    Some(Box::new(highlight)),
+                    &mut completer,
+                );
+                match next {
+                    Ok(next) => join(&mut input, &next.replace("\\\n", " ")),
+                    // Ctrl-C or Ctrl-D abandons the whole line.
+                    Err(_) => continue 'lines,
                 }
             }
-            con.history.push(input.into()).unwrap();
-        }
-    } else {
-        for (line_offset, input) in stdin().lock().lines().enumerate() {
-            let input = match input {
-                Ok(i) => i,
-                Err(e) => {
-                    eprintln!("unexpected IO error {}", e);
-                    std::process::exit(2);
+            if let Err(errors) = calculator.parse(&input) {
+                // The errors can only point into a continued line once it's shown joined up.
+                if continued {
+                    eprintln!("{}{}", " ".repeat(indent), input);
                 }
-            };
-            match calculator.parse(&input) {
-                Ok(_) => (),
-                Err(TokenError { message, span }) => {
-                    let linum_str = format!("{}", line_offset + 1);
+                // Each error is on a line of its own, lined up with the input.
+                for TokenError { error, span } in errors {
                     eprintln!(
-                        "{}: {}\n{}{}{} {}{}",
-                        linum_str,
-                        input,
-                        " ".repeat(span.start + linum_str.len() + 2),
-                        color::LightRed.fg_str(),
-                        "^".repeat(span.len()),
-                        message,
-                        color::Reset.fg_str(),
-                    );
-                    std::process::exit(1);
+                        "{}{}",
+                        " ".repeat(span.start + indent),
+                        paint(
+                            &format!("{} {}", "^".repeat(span.len()), error),
+                            error_color
+                        ),
+                    )
+                }
+            }
+            if calculator.take_browse() {
+                if let Err(e) = browse::browse(&mut calculator) {
+                    eprintln!("could not browse the stack: {}", e);
+                }
+            }
+            apply_keymap(&mut con, &mut calculator);
+            print_output(&mut calculator);
+            print_stack(&calculator);
+            // With a history file, this appends the line to it.
+            if let Err(e) = con.history.push(input.into()) {
+                match &history {
+                    Some(path) => eprintln!("could not save history to {}: {}", path.display(), e),
+                    None => eprintln!("could not save history: {}", e),
                 }
             }
         }
-        for num in &calculator.stack {
-            let (num, den) = num.clone().into_parts();
-            if den.is_one() {
-                println!("{num} (0x{num:x})", num = num);
-            } else {
-                println!("{num}/{den} (0x{num:x}/{den:x})", num = num, den = den,);
+        if let Some(path) = &state {
+            if let Err(e) = save_state(&calculator, path) {
+                eprintln!("could not save the session to {}: {}", path.display(), e);
             }
         }
+    } else {
+        run_lines(
+            &mut calculator,
+            args.output,
+            stdin().lock(),
+            |line_offset, _| format!("{}", line_offset + 1),
+        );
+        if args.output == Output::Text {
+            print_stack(&calculator);
+        }
     }
     Ok(())
- }
+}
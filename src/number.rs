@@ -0,0 +1,14 @@
+//! The big number types that the calculator is built on.
+//!
+//! Every module takes its integers and rationals from here rather than from a particular crate,
+//! so that the backend is chosen in one place by a Cargo feature. By default the calculator
+//! builds on `num-bigint` and `num-rational`, which `bigint` wraps in the same interface as
+//! ramp's, on stable and without `std`. The `ramp` feature uses `ramp` instead, which needs a
+//! nightly compiler and `std`.
+#[cfg(not(feature = "ramp"))]
+mod bigint;
+
+#[cfg(not(feature = "ramp"))]
+pub use bigint::{Int, ParseIntError, RandomInt, Rational};
+#[cfg(feature = "ramp")]
+pub use ramp::{int::ParseIntError, rational::Rational, Int, RandomInt};
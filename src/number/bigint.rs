@@ -0,0 +1,739 @@
+//! The backend built on the `num` crates, which needs neither nightly nor `std`.
+//!
+//! `Int` and `Rational` wrap `num-bigint` and `num-rational` in the part of ramp's interface that
+//! the calculator uses, so that the other modules can't tell the backends apart. Where the two
+//! crates disagree, these follow ramp: `>>` shifts the magnitude of a negative number, so it
+//! rounds toward zero, and `pow_mod` keeps the sign of the base.
+use alloc::string::String;
+use alloc::vec;
+use core::fmt;
+use core::ops::{
+    Add, AddAssign, BitAnd, BitOr, BitXor, Div, DivAssign, Mul, MulAssign, Neg, Not, Rem,
+    RemAssign, Shl, ShlAssign, Shr, ShrAssign, Sub, SubAssign,
+};
+use core::str::FromStr;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, Zero};
+use rand::Rng;
+
+/// An integer of any size.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Int(BigInt);
+
+/// A fraction of two `Int`s, always kept in lowest terms.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rational(BigRational);
+
+/// Why a string isn't an integer, with the same messages as ramp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseIntError {
+    empty: bool,
+}
+
+impl fmt::Display for ParseIntError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.empty {
+            f.write_str("cannot parse empty string")
+        } else {
+            f.write_str("invalid digit found in string")
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseIntError {}
+
+impl Int {
+    pub fn zero() -> Int {
+        Int(BigInt::zero())
+    }
+
+    pub fn one() -> Int {
+        Int(BigInt::one())
+    }
+
+    /// -1, 0 or 1, as the number is negative, zero or positive.
+    pub fn sign(&self) -> i32 {
+        match self.0.sign() {
+            Sign::Minus => -1,
+            Sign::NoSign => 0,
+            Sign::Plus => 1,
+        }
+    }
+
+    pub fn abs(self) -> Int {
+        Int(self.0.abs())
+    }
+
+    pub fn negate(&mut self) {
+        self.0 = -core::mem::take(&mut self.0);
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.0.is_even()
+    }
+
+    pub fn to_str_radix(&self, base: u8, upper: bool) -> String {
+        let digits = self.0.to_str_radix(u32::from(base));
+        if upper {
+            digits.to_uppercase()
+        } else {
+            digits
+        }
+    }
+
+    /// Parse `src` in `base`, which, unlike `num-bigint`, allows neither a `+` nor underscores.
+    pub fn from_str_radix(src: &str, base: u8) -> Result<Int, ParseIntError> {
+        let digits = src.strip_prefix('-').unwrap_or(src);
+        if digits.is_empty() {
+            return Err(ParseIntError { empty: true });
+        }
+        if !digits.chars().all(|c| c.is_digit(u32::from(base))) {
+            return Err(ParseIntError { empty: false });
+        }
+        BigInt::parse_bytes(src.as_bytes(), u32::from(base))
+            .map(Int)
+            .ok_or(ParseIntError { empty: false })
+    }
+
+    /// The quotient and remainder, both truncated toward zero like `/` and `%`.
+    pub fn divmod(&self, other: &Int) -> (Int, Int) {
+        let (quotient, remainder) = self.0.div_rem(&other.0);
+        (Int(quotient), Int(remainder))
+    }
+
+    pub fn pow(&self, exp: usize) -> Int {
+        Int(Pow::pow(&self.0, exp))
+    }
+
+    pub fn square(&self) -> Int {
+        Int(&self.0 * &self.0)
+    }
+
+    /// `self` to the power `exp`, modulo `modulus`, with the sign of `self % modulus`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `exp` is negative or `modulus` is zero.
+    pub fn pow_mod(&self, exp: &Int, modulus: &Int) -> Int {
+        assert!(!modulus.is_zero(), "Got a zero modulus");
+        assert!(exp.sign() >= 0, "Got a negative exponent: {}", exp);
+        let base = self % modulus;
+        let mut result = Int::one() % modulus;
+        if exp.is_zero() || base.is_zero() {
+            return if exp.is_zero() { result } else { base };
+        }
+        for i in (0..exp.bit_length()).rev() {
+            result = result.square() % modulus;
+            if exp.bit(i) {
+                result = (result * &base) % modulus;
+            }
+        }
+        result
+    }
+
+    /// The square root, rounded down, and the remainder, or `None` if the number is negative.
+    pub fn sqrt_rem(self) -> Option<(Int, Int)> {
+        if self.sign() < 0 {
+            return None;
+        }
+        let root = self.0.sqrt();
+        let remainder = &self.0 - &root * &root;
+        Some((Int(root), Int(remainder)))
+    }
+
+    pub fn gcd(&self, other: &Int) -> Int {
+        Int(self.0.gcd(&other.0))
+    }
+
+    pub fn lcm(&self, other: &Int) -> Int {
+        Int(self.0.lcm(&other.0))
+    }
+
+    /// The number of bits in the magnitude, which is one for zero.
+    pub fn bit_length(&self) -> u32 {
+        if self.is_zero() {
+            1
+        } else {
+            self.0.bits() as u32
+        }
+    }
+
+    /// Bit `bit` of the number in two's complement.
+    pub fn bit(&self, bit: u32) -> bool {
+        self.0.bit(u64::from(bit))
+    }
+
+    /// The number of set bits, which is `usize::MAX` for a negative number.
+    pub fn count_ones(&self) -> usize {
+        if self.sign() < 0 {
+            usize::MAX
+        } else {
+            self.0.magnitude().count_ones() as usize
+        }
+    }
+
+    /// The number of zeros below the lowest set bit, which is zero for zero.
+    pub fn trailing_zeros(&self) -> u32 {
+        self.0.trailing_zeros().unwrap_or(0) as u32
+    }
+
+    /// The lowest 128 bits of the magnitude.
+    fn low_bits(&self) -> u128 {
+        let digits = self.0.magnitude().to_u64_digits();
+        let digit = |i: usize| u128::from(digits.get(i).copied().unwrap_or(0));
+        digit(0) | digit(1) << 64
+    }
+}
+
+impl Rational {
+    /// The fraction `n / d` in lowest terms.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `d` is zero.
+    pub fn new(n: Int, d: Int) -> Rational {
+        Rational(BigRational::new(n.0, d.0))
+    }
+
+    /// The numerator and the denominator, which is positive.
+    pub fn into_parts(self) -> (Int, Int) {
+        let (n, d) = self.0.into();
+        (Int(n), Int(d))
+    }
+
+    /// Does nothing, as the fraction is always in lowest terms.
+    pub fn normalize(&mut self) {}
+
+    /// # Panics
+    ///
+    /// Panics if the fraction is zero.
+    pub fn invert(self) -> Rational {
+        Rational(self.0.recip())
+    }
+
+    /// The nearest `Int`, rounding halves away from zero.
+    pub fn round(self) -> Int {
+        Int(self.0.round().to_integer())
+    }
+
+    pub fn sign(&self) -> i32 {
+        Int(self.0.numer().clone()).sign()
+    }
+
+    pub fn abs(self) -> Rational {
+        Rational(self.0.abs())
+    }
+}
+
+/// Random `Int`s from any generator.
+pub trait RandomInt {
+    /// A uniformly random number from zero up to `bound`, excluding `bound`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bound` isn't positive.
+    fn gen_uint_below(&mut self, bound: &Int) -> Int;
+
+    /// A uniformly random number from `lo` up to `hi`, excluding `hi`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lo` isn't below `hi`.
+    fn gen_int_range(&mut self, lo: &Int, hi: &Int) -> Int;
+}
+
+impl<R: Rng> RandomInt for R {
+    fn gen_uint_below(&mut self, bound: &Int) -> Int {
+        assert!(bound.sign() > 0, "Bound is not positive");
+        // Draw as many bits as the bound has until the number is below it, which takes less than
+        // two tries on average.
+        let bits = bound.bit_length();
+        let mut digits = vec![0; (bits as usize).div_ceil(32)];
+        loop {
+            for digit in digits.iter_mut() {
+                *digit = self.gen::<u32>();
+            }
+            if !bits.is_multiple_of(32) {
+                *digits.last_mut().unwrap() >>= 32 - bits % 32;
+            }
+            let num = Int(BigInt::from(BigUint::new(digits.clone())));
+            if num < *bound {
+                return num;
+            }
+        }
+    }
+
+    fn gen_int_range(&mut self, lo: &Int, hi: &Int) -> Int {
+        assert!(lo < hi, "Empty range");
+        self.gen_uint_below(&(hi - lo)) + lo
+    }
+}
+
+impl FromStr for Int {
+    type Err = ParseIntError;
+
+    fn from_str(src: &str) -> Result<Int, ParseIntError> {
+        Int::from_str_radix(src, 10)
+    }
+}
+
+macro_rules! impl_fmt {
+    ($($Trait:ident),*) => {
+        $(impl fmt::$Trait for Int {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::$Trait::fmt(&self.0, f)
+            }
+        })*
+    };
+}
+
+impl_fmt!(Display, Debug, Binary, Octal, LowerHex, UpperHex);
+
+impl fmt::Debug for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Implement the conversions between `Int` and the signed `$t`s and unsigned `$u`s.
+macro_rules! impl_from_prim {
+    ($($t:ty),* ; $($u:ty),*) => {
+        impl_from_prim!(@each $($t => |low: $t| low.wrapping_neg()),*, $($u => |low: $u| low),*);
+    };
+    (@each $($t:ty => $negative:expr),*) => {
+        $(impl From<$t> for Int {
+            fn from(num: $t) -> Int {
+                Int(BigInt::from(num))
+            }
+        }
+
+        /// The lowest bits of the magnitude, which a signed type negates if the number is
+        /// negative, as ramp does.
+        impl From<&Int> for $t {
+            #[allow(clippy::unnecessary_cast)]
+            fn from(num: &Int) -> $t {
+                let low = num.low_bits() as $t;
+                if num.sign() < 0 {
+                    ($negative)(low)
+                } else {
+                    low
+                }
+            }
+        })*
+    };
+}
+
+/// Implement the comparisons between `Int` and the `$t`s.
+macro_rules! impl_cmp_prim {
+    ($($t:ty),*) => {
+        $(impl PartialEq<$t> for Int {
+            fn eq(&self, other: &$t) -> bool {
+                self.0 == BigInt::from(*other)
+            }
+        }
+
+        impl PartialOrd<$t> for Int {
+            fn partial_cmp(&self, other: &$t) -> Option<core::cmp::Ordering> {
+                self.0.partial_cmp(&BigInt::from(*other))
+            }
+        }
+
+        impl PartialEq<Int> for $t {
+            fn eq(&self, other: &Int) -> bool {
+                BigInt::from(*self) == other.0
+            }
+        }
+
+        impl PartialOrd<Int> for $t {
+            fn partial_cmp(&self, other: &Int) -> Option<core::cmp::Ordering> {
+                BigInt::from(*self).partial_cmp(&other.0)
+            }
+        })*
+    };
+}
+
+/// Implement the arithmetic and bitwise operators between `Int` and owned `$t`s, and nothing
+/// with borrowed `$t`s, which would leave the type of `x & &y` ambiguous.
+macro_rules! impl_arith_prim {
+    ($($t:ty),*) => {
+        $(impl_arith_prim!(@each $t,
+            Add add +, Sub sub -, Mul mul *, Div div /, Rem rem %,
+            BitAnd bitand &, BitOr bitor |, BitXor bitxor ^);
+        impl_arith_prim!(@assign $t,
+            AddAssign add_assign +, SubAssign sub_assign -, MulAssign mul_assign *,
+            DivAssign div_assign /);)*
+    };
+    (@each $t:ty, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$t> for Int {
+            type Output = Int;
+
+            fn $method(self, other: $t) -> Int {
+                Int(self.0 $op BigInt::from(other))
+            }
+        }
+
+        impl $Trait<$t> for &Int {
+            type Output = Int;
+
+            fn $method(self, other: $t) -> Int {
+                Int(&self.0 $op BigInt::from(other))
+            }
+        }
+
+        impl $Trait<Int> for $t {
+            type Output = Int;
+
+            fn $method(self, other: Int) -> Int {
+                Int(BigInt::from(self) $op other.0)
+            }
+        }
+
+        impl $Trait<&Int> for $t {
+            type Output = Int;
+
+            fn $method(self, other: &Int) -> Int {
+                Int(BigInt::from(self) $op &other.0)
+            }
+        })*
+    };
+    (@assign $t:ty, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$t> for Int {
+            fn $method(&mut self, other: $t) {
+                *self = &*self $op other;
+            }
+        })*
+    };
+}
+
+/// Implement the arithmetic and bitwise operators between owned and borrowed `$Lhs` and `$Rhs`,
+/// on the `BigInt`s that `$lhs` and `$rhs` take from the two sides.
+macro_rules! impl_binops {
+    ($Lhs:ty, $Rhs:ty, $lhs:ident, $rhs:ident) => {
+        impl_binops!(@each $Lhs, $Rhs, $lhs, $rhs,
+            Add add +, Sub sub -, Mul mul *, Div div /, Rem rem %,
+            BitAnd bitand &, BitOr bitor |, BitXor bitxor ^);
+    };
+    (@each $Lhs:ty, $Rhs:ty, $lhs:ident, $rhs:ident, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$Rhs> for $Lhs {
+            type Output = Int;
+
+            fn $method(self, other: $Rhs) -> Int {
+                Int($lhs(&self) $op $rhs(&other))
+            }
+        }
+
+        impl $Trait<&$Rhs> for $Lhs {
+            type Output = Int;
+
+            fn $method(self, other: &$Rhs) -> Int {
+                Int($lhs(&self) $op $rhs(other))
+            }
+        }
+
+        impl $Trait<$Rhs> for &$Lhs {
+            type Output = Int;
+
+            fn $method(self, other: $Rhs) -> Int {
+                Int($lhs(self) $op $rhs(&other))
+            }
+        }
+
+        impl $Trait<&$Rhs> for &$Lhs {
+            type Output = Int;
+
+            fn $method(self, other: &$Rhs) -> Int {
+                Int($lhs(self) $op $rhs(other))
+            }
+        })*
+    };
+}
+
+/// Implement the assigning arithmetic operators on `$Lhs` with owned and borrowed `$Rhs`.
+macro_rules! impl_assign_ops {
+    ($Lhs:ty, $Rhs:ty) => {
+        impl_assign_ops!(@each $Lhs, $Rhs,
+            AddAssign add_assign +, SubAssign sub_assign -, MulAssign mul_assign *,
+            DivAssign div_assign /);
+    };
+    (@each $Lhs:ty, $Rhs:ty, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$Rhs> for $Lhs {
+            fn $method(&mut self, other: $Rhs) {
+                *self = &*self $op &other;
+            }
+        }
+
+        impl $Trait<&$Rhs> for $Lhs {
+            fn $method(&mut self, other: &$Rhs) {
+                *self = &*self $op other;
+            }
+        })*
+    };
+}
+
+fn inner(num: &Int) -> &BigInt {
+    &num.0
+}
+
+impl_binops!(Int, Int, inner, inner);
+impl_assign_ops!(Int, Int);
+impl_from_prim!(i8, i16, i32, i64, i128, isize; u8, u16, u32, u64, u128, usize);
+impl_cmp_prim!(i32, i64, u64, usize);
+impl_arith_prim!(i32, u64, usize);
+
+impl RemAssign<Int> for Int {
+    fn rem_assign(&mut self, other: Int) {
+        *self = &*self % other;
+    }
+}
+
+impl RemAssign<&Int> for Int {
+    fn rem_assign(&mut self, other: &Int) {
+        *self = &*self % other;
+    }
+}
+
+impl Neg for Int {
+    type Output = Int;
+
+    fn neg(self) -> Int {
+        Int(-self.0)
+    }
+}
+
+impl Neg for &Int {
+    type Output = Int;
+
+    fn neg(self) -> Int {
+        Int(-&self.0)
+    }
+}
+
+impl Not for Int {
+    type Output = Int;
+
+    fn not(self) -> Int {
+        Int(!self.0)
+    }
+}
+
+impl Not for &Int {
+    type Output = Int;
+
+    fn not(self) -> Int {
+        Int(!&self.0)
+    }
+}
+
+impl Shl<usize> for Int {
+    type Output = Int;
+
+    fn shl(self, bits: usize) -> Int {
+        Int(self.0 << bits)
+    }
+}
+
+impl Shl<usize> for &Int {
+    type Output = Int;
+
+    fn shl(self, bits: usize) -> Int {
+        Int(&self.0 << bits)
+    }
+}
+
+/// Shifts the magnitude, so a negative number rounds toward zero rather than down.
+impl Shr<usize> for Int {
+    type Output = Int;
+
+    fn shr(self, bits: usize) -> Int {
+        &self >> bits
+    }
+}
+
+impl Shr<usize> for &Int {
+    type Output = Int;
+
+    fn shr(self, bits: usize) -> Int {
+        if self.sign() < 0 {
+            Int(-((-&self.0) >> bits))
+        } else {
+            Int(&self.0 >> bits)
+        }
+    }
+}
+
+impl ShlAssign<usize> for Int {
+    fn shl_assign(&mut self, bits: usize) {
+        *self = &*self << bits;
+    }
+}
+
+impl ShrAssign<usize> for Int {
+    fn shr_assign(&mut self, bits: usize) {
+        *self = &*self >> bits;
+    }
+}
+
+impl Zero for Int {
+    fn zero() -> Int {
+        Int::zero()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl One for Int {
+    fn one() -> Int {
+        Int::one()
+    }
+}
+
+impl<U: Into<Int>> From<U> for Rational {
+    fn from(num: U) -> Rational {
+        Rational(BigRational::from_integer(num.into().0))
+    }
+}
+
+impl PartialEq<Int> for Rational {
+    fn eq(&self, other: &Int) -> bool {
+        self.0.is_integer() && *self.0.numer() == other.0
+    }
+}
+
+impl PartialEq<Rational> for Int {
+    fn eq(&self, other: &Rational) -> bool {
+        other == self
+    }
+}
+
+impl PartialOrd<Int> for Rational {
+    fn partial_cmp(&self, other: &Int) -> Option<core::cmp::Ordering> {
+        self.0
+            .partial_cmp(&BigRational::from_integer(other.0.clone()))
+    }
+}
+
+impl PartialOrd<Rational> for Int {
+    fn partial_cmp(&self, other: &Rational) -> Option<core::cmp::Ordering> {
+        other.partial_cmp(self).map(core::cmp::Ordering::reverse)
+    }
+}
+
+/// Implement the arithmetic operators between owned and borrowed `$Lhs` and `$Rhs`, on the
+/// `BigRational`s that `$lhs` and `$rhs` make from the two sides.
+macro_rules! impl_rational_ops {
+    ($Lhs:ty, $Rhs:ty, $lhs:ident, $rhs:ident) => {
+        impl_rational_ops!(@each $Lhs, $Rhs, $lhs, $rhs, Add add +, Sub sub -, Mul mul *, Div div /);
+    };
+    (@each $Lhs:ty, $Rhs:ty, $lhs:ident, $rhs:ident, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$Rhs> for $Lhs {
+            type Output = Rational;
+
+            fn $method(self, other: $Rhs) -> Rational {
+                Rational($lhs(&self) $op $rhs(&other))
+            }
+        }
+
+        impl $Trait<&$Rhs> for $Lhs {
+            type Output = Rational;
+
+            fn $method(self, other: &$Rhs) -> Rational {
+                Rational($lhs(&self) $op $rhs(other))
+            }
+        }
+
+        impl $Trait<$Rhs> for &$Lhs {
+            type Output = Rational;
+
+            fn $method(self, other: $Rhs) -> Rational {
+                Rational($lhs(self) $op $rhs(&other))
+            }
+        }
+
+        impl $Trait<&$Rhs> for &$Lhs {
+            type Output = Rational;
+
+            fn $method(self, other: &$Rhs) -> Rational {
+                Rational($lhs(self) $op $rhs(other))
+            }
+        })*
+    };
+}
+
+/// Implement the assigning arithmetic operators on `Rational` with owned and borrowed `$Rhs`.
+macro_rules! impl_rational_assign_ops {
+    ($Rhs:ty) => {
+        impl_rational_assign_ops!(@each $Rhs,
+            AddAssign add_assign +, SubAssign sub_assign -, MulAssign mul_assign *,
+            DivAssign div_assign /);
+    };
+    (@each $Rhs:ty, $($Trait:ident $method:ident $op:tt),*) => {
+        $(impl $Trait<$Rhs> for Rational {
+            fn $method(&mut self, other: $Rhs) {
+                *self = &*self $op &other;
+            }
+        }
+
+        impl $Trait<&$Rhs> for Rational {
+            fn $method(&mut self, other: &$Rhs) {
+                *self = &*self $op other;
+            }
+        })*
+    };
+}
+
+fn fraction(num: &Rational) -> &BigRational {
+    &num.0
+}
+
+fn ratio(num: &Int) -> BigRational {
+    BigRational::from_integer(num.0.clone())
+}
+
+impl_rational_ops!(Rational, Rational, fraction, fraction);
+impl_rational_ops!(Rational, Int, fraction, ratio);
+impl_rational_ops!(Int, Rational, ratio, fraction);
+impl_rational_assign_ops!(Rational);
+impl_rational_assign_ops!(Int);
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational(-self.0)
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational(-&self.0)
+    }
+}
+
+impl Zero for Rational {
+    fn zero() -> Rational {
+        Rational(BigRational::zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl One for Rational {
+    fn one() -> Rational {
+        Rational(BigRational::one())
+    }
+}
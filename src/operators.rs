@@ -0,0 +1,266 @@
+//! The operator registry.
+//!
+//! Every operator and command is listed here once, along with its stack effect and a
+//! description. Lexing, tab completion and `help` all read from this table, so an operator
+//! can't be added to one without the others.
+use crate::Token::{self, *};
+use crate::{
+    parse_add_to_register, parse_exchange, parse_group, parse_lenient, parse_load, parse_max_bits,
+    parse_order, parse_precision, parse_recall_register, parse_save, parse_seed, parse_set,
+    parse_show, parse_store_register, parse_switch, parse_trace, parse_unit, parse_word_size,
+    Angle, ArgumentParser, Base, Constant, Notation, Statistic,
+};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// How an operator is read.
+pub enum Syntax {
+    /// The operator is a single token.
+    Token(Token),
+    /// The operator consumes the following word as its argument.
+    Command(ArgumentParser),
+}
+
+/// An entry in the registry.
+pub struct Operator {
+    /// Every spelling of the operator, such as `%%` and `mod`.
+    pub names: &'static [&'static str],
+    pub syntax: Syntax,
+    /// The stack effect, in Forth notation.
+    pub effect: &'static str,
+    pub description: &'static str,
+}
+
+const fn op(
+    names: &'static [&'static str],
+    token: Token,
+    effect: &'static str,
+    description: &'static str,
+) -> Operator {
+    Operator {
+        names,
+        syntax: Syntax::Token(token),
+        effect,
+        description,
+    }
+}
+
+const fn command(
+    names: &'static [&'static str],
+    parse: ArgumentParser,
+    effect: &'static str,
+    description: &'static str,
+) -> Operator {
+    Operator {
+        names,
+        syntax: Syntax::Command(parse),
+        effect,
+        description,
+    }
+}
+
+pub const OPERATORS: &[Operator] = &[
+    op(&["+"], Plus, "(a b -- a+b)", "Add"),
+    op(&["-"], Minus, "(a b -- a-b)", "Subtract"),
+    op(&["*"], Times, "(a b -- a*b)", "Multiply"),
+    op(&["/"], Divide, "(a b -- a/b)", "Divide"),
+    op(&["%%", "mod"], Modulo, "(a b -- a%b)", "Remainder, with the sign of b"),
+    op(&["^"], Exp, "(a n -- a^n)", "Raise to an integer power"),
+    op(&["abs"], Abs, "(a -- |a|)", "Absolute value"),
+    op(&["neg"], Negate, "(a -- -a)", "Negate"),
+    op(&["inv"], Invert, "(a -- 1/a)", "Reciprocal"),
+    op(&["gcd"], Gcd, "(a b -- c)", "Greatest common divisor"),
+    op(&["powmod"], PowerMod, "(a b m -- c)", "a to the power b, modulo m"),
+    op(&["invmod"], InverseMod, "(a m -- b)", "The inverse of a modulo m, so that a b * m % is 1"),
+    op(&["isprime"], IsPrime, "(a -- flag)", "1 if a is prime, else 0"),
+    op(&["nextprime"], NextPrime, "(a -- p)", "The smallest prime greater than a"),
+    op(&["prevprime"], PreviousPrime, "(a -- p)", "The largest prime less than a"),
+    op(&["factor"], Factor, "(a -- p1 ... pn)", "The prime factors of a, smallest first and repeated"),
+    op(&["lcm"], Lcm, "(a b -- c)", "Least common multiple"),
+    op(&["_", "round"], Round, "(a -- b)", "Round to the nearest integer, halves away from zero"),
+    op(&["floor"], Floor, "(a -- b)", "Round towards negative infinity"),
+    op(&["ceil"], Ceil, "(a -- b)", "Round towards positive infinity"),
+    op(&["trunc"], Trunc, "(a -- b)", "Round towards zero"),
+    op(&["frac"], Frac, "(a -- b)", "Fractional part, with the sign of a"),
+    op(&["sqrt"], Sqrt, "(a -- b)", "Square root"),
+    op(&["root"], Root, "(a n -- b)", "nth root"),
+    op(&["sin"], Sin, "(a -- b)", "Sine"),
+    op(&["cos"], Cos, "(a -- b)", "Cosine"),
+    op(&["tan"], Tan, "(a -- b)", "Tangent"),
+    op(&["asin"], Asin, "(a -- b)", "Inverse sine"),
+    op(&["acos"], Acos, "(a -- b)", "Inverse cosine"),
+    op(&["atan"], Atan, "(a -- b)", "Inverse tangent"),
+    op(&["exp"], Exponential, "(a -- e^a)", "e to the power of a"),
+    op(&["ln"], Ln, "(a -- b)", "Natural logarithm"),
+    op(&["log2"], Log2, "(a -- b)", "Base 2 logarithm"),
+    op(&["log10"], Log10, "(a -- b)", "Base 10 logarithm"),
+    op(&["logb"], Logb, "(a b -- c)", "Base b logarithm of a"),
+    op(&["fact"], Factorial, "(n -- n!)", "Factorial"),
+    op(&["choose"], Choose, "(n k -- c)", "Ways to choose k of n items, ignoring order"),
+    op(&["perm"], Permute, "(n k -- p)", "Ways to arrange k of n items"),
+    op(&["pct"], Percent, "(n -- n/100)", "Percent as a fraction"),
+    op(&["pctof"], PercentOf, "(a n -- a*n/100)", "n percent of a"),
+    op(&["lt"], Less, "(a b -- a<b)", "1 if a is less than b, otherwise 0"),
+    op(&["gt"], Greater, "(a b -- a>b)", "1 if a is greater than b, otherwise 0"),
+    op(&["le"], LessEqual, "(a b -- a<=b)", "1 if a is at most b, otherwise 0"),
+    op(&["ge"], GreaterEqual, "(a b -- a>=b)", "1 if a is at least b, otherwise 0"),
+    op(&["eq"], Equal, "(a b -- a==b)", "1 if a equals b, otherwise 0"),
+    op(&["ne"], NotEqual, "(a b -- a!=b)", "1 if a differs from b, otherwise 0"),
+    op(&["&"], And, "(a b -- c)", "Bitwise and"),
+    op(&["|"], Or, "(a b -- c)", "Bitwise or"),
+    op(&["xor"], Xor, "(a b -- c)", "Bitwise exclusive or"),
+    op(&["~", "not"], Not, "(a -- b)", "Bitwise complement"),
+    op(&["<<"], ShiftLeft, "(a n -- b)", "Shift left by n bits"),
+    op(&["bset"], BitSet, "(a n -- b)", "Set bit n"),
+    op(&["bclr"], BitClear, "(a n -- b)", "Clear bit n"),
+    op(&["btgl"], BitToggle, "(a n -- b)", "Toggle bit n"),
+    op(&["btst"], BitTest, "(a n -- bit)", "1 if bit n is set, else 0"),
+    op(&["mask"], Mask, "(width shift -- mask)", "width one bits, shifted left by shift"),
+    op(&["maskrange"], MaskRange, "(high low -- mask)", "One bits from bit high down to bit low"),
+    op(&["bext"], BitExtract, "(a high low -- field)", "Bits high down to low of a"),
+    op(&["bins"], BitInsert, "(a field high low -- b)", "Replace bits high down to low of a with field"),
+    op(&["popcnt"], PopCount, "(a -- n)", "Number of one bits"),
+    op(&["clz"], LeadingZeros, "(a -- n)", "Number of zero bits above the highest one bit in the word"),
+    op(&["ctz"], TrailingZeros, "(a -- n)", "Number of zero bits below the lowest one bit"),
+    op(&["bswap"], ByteSwap(None), "(a -- b)", "Reverse the order of the bytes in the word"),
+    op(&["bswap16"], ByteSwap(Some(16)), "(a -- b)", "Reverse the order of the bytes in the low 16 bits"),
+    op(&["bswap32"], ByteSwap(Some(32)), "(a -- b)", "Reverse the order of the bytes in the low 32 bits"),
+    op(&["bswap64"], ByteSwap(Some(64)), "(a -- b)", "Reverse the order of the bytes in the low 64 bits"),
+    op(&[">>"], ShiftRight, "(a n -- b)", "Shift right by n bits, rounding down"),
+    op(&["pi"], Const(Constant::Pi), "( -- pi)", "The ratio of a circle's circumference to its diameter"),
+    op(&["e"], Const(Constant::E), "( -- e)", "The base of the natural logarithm"),
+    op(&["phi"], Const(Constant::Phi), "( -- phi)", "The golden ratio"),
+    op(&["sqrt2"], Const(Constant::Sqrt2), "( -- sqrt2)", "The square root of two"),
+    op(&["ln2"], Const(Constant::Ln2), "( -- ln2)", "The natural logarithm of two"),
+    op(&["<", "dup"], Duplicate, "(a -- a a)", "Duplicate the top of the stack"),
+    op(&["!", "drop"], Drop, "(a --)", "Drop the top of the stack"),
+    op(&["swap"], Swap, "(a b -- b a)", "Swap the top two entries"),
+    op(&["over"], Over, "(a b -- a b a)", "Copy the second entry to the top"),
+    op(&["nip"], Nip, "(a b -- b)", "Drop the second entry"),
+    op(&["tuck"], Tuck, "(a b -- b a b)", "Copy the top entry beneath the second"),
+    op(&["rot"], Rot, "(a b c -- b c a)", "Rotate the third entry to the top"),
+    op(&["pick"], Pick, "(xu ... x0 u -- xu ... x0 xu)", "Copy the uth entry to the top"),
+    op(&["roll"], Roll, "(xu ... x0 u -- ... x0 xu)", "Move the uth entry to the top"),
+    op(&["dropn"], DropN, "(xn ... x1 n --)", "Drop the top n entries"),
+    op(&["keep"], Keep, "(... xn ... x1 n -- xn ... x1)", "Drop all but the top n entries"),
+    op(&["dupn"], DupN, "(xn ... x1 n -- xn ... x1 xn ... x1)", "Duplicate the top n entries"),
+    command(&["to"], parse_unit, "(a -- b)", "Convert to another unit: `to km/h`"),
+    op(&["copy"], Copy(None), "(xu ... x0 -- xu ... x0)", "Copy the top entry, or the uth with `copy u`, to the clipboard as displayed"),
+    op(&["%"], Empty, "(... --)", "Clear the stack"),
+    command(&["stack"], parse_switch, "( -- )", "Switch to another stack, creating it if it's new: `stack work`"),
+    command(&["xchg"], parse_exchange, "(a -- b)", "Exchange the top entry with the top of another stack, or move it there if that's empty: `xchg work`"),
+    op(&["ans"], Answer, "( -- a)", "The top entry as the last line left it"),
+    op(&["results"], Results, "( -- )", "List the top entry after each line that changed the stack, to recall as `$1`, `$2`, ..."),
+    op(&["depth"], Depth, "( -- n)", "The number of entries on the stack"),
+    op(&["sum"], Sum, "(... -- a)", "Add up every entry on the stack"),
+    op(&["prod"], Product, "(... -- a)", "Multiply every entry on the stack"),
+    op(&["min"], Min, "(a b -- c)", "The lesser of a and b"),
+    op(&["max"], Max, "(a b -- c)", "The greater of a and b"),
+    op(&["minall"], MinAll, "(... -- a)", "The least entry on the stack"),
+    op(&["maxall"], MaxAll, "(... -- a)", "The greatest entry on the stack"),
+    op(&["rev"], Reverse, "(... -- ...)", "Reverse the order of the stack"),
+    op(&["sort"], Sort, "(... -- ...)", "Sort the stack, least at the bottom and greatest on top"),
+    op(&["rsort"], ReverseSort, "(... -- ...)", "Sort the stack, greatest at the bottom and least on top"),
+    op(&["rand"], Random, "( -- r)", "A random number from 0 up to 1, with as many digits as the precision"),
+    op(&["randint"], RandomInt, "(lo hi -- n)", "A random integer from lo to hi, including both"),
+    command(&["seed"], parse_seed, "( -- )", "Seed the random numbers, making them repeatable: `seed 42`"),
+    op(&["mean"], Summary(Statistic::Mean), "(... -- a)", "Mean of every entry on the stack"),
+    op(&["median"], Summary(Statistic::Median), "(... -- a)", "Median of every entry on the stack"),
+    op(&["var"], Summary(Statistic::Variance), "(... -- a)", "Sample variance of every entry on the stack"),
+    op(&["sdev"], Summary(Statistic::StdDev), "(... -- a)", "Sample standard deviation of every entry on the stack"),
+    op(&["nmean"], SummaryOf(Statistic::Mean), "(xn ... x1 n -- a)", "Mean of the top n entries"),
+    op(&["nmedian"], SummaryOf(Statistic::Median), "(xn ... x1 n -- a)", "Median of the top n entries"),
+    op(&["nvar"], SummaryOf(Statistic::Variance), "(xn ... x1 n -- a)", "Sample variance of the top n entries"),
+    op(&["nsdev"], SummaryOf(Statistic::StdDev), "(xn ... x1 n -- a)", "Sample standard deviation of the top n entries"),
+    op(&["undo"], Undo, "(... -- ...)", "Revert the stack, variables and registers to before the last line"),
+    op(&["redo"], Redo, "(... -- ...)", "Reapply the line reverted by `undo`"),
+    command(&["sto"], parse_store_register, "(a -- a)", "Store in a numbered register: `sto 3`"),
+    command(&["rcl"], parse_recall_register, "( -- a)", "Recall a numbered register, which is 0 until stored to: `rcl 3`"),
+    command(&["sto+"], parse_add_to_register, "(a -- a)", "Add to a numbered register: `sto+ 3`"),
+    op(&[":"], Define, "( -- )", "Define a word: `: name ... ;`"),
+    op(&[";"], EndDefine, "( -- )", "End a word definition"),
+    op(&["if"], If, "(flag -- )", "Run what follows if flag isn't 0: `if ... else ... then`"),
+    op(&["else"], Else, "( -- )", "Start what `if` runs when its flag is 0"),
+    op(&["then"], Then, "( -- )", "End an `if`"),
+    op(&["do"], Do, "(n -- )", "Run what follows n times: `do ... loop`"),
+    op(&["loop"], Loop, "( -- )", "End a `do`"),
+    op(&["i"], Index, "( -- i)", "The iteration of the innermost loop, counting from 0"),
+    command(&["word"], parse_word_size, "( -- )", "Set the word size: `word 32` or `word unlimited`"),
+    command(&["maxbits"], parse_max_bits, "( -- )", "Limit the bits in a result: `maxbits 100000` or `maxbits unlimited`"),
+    command(&["prec"], parse_precision, "( -- )", "Set the digits of precision: `prec 50`"),
+    op(&["rad"], Angles(Angle::Radians), "( -- )", "Measure angles in radians"),
+    op(&["deg"], Angles(Angle::Degrees), "( -- )", "Measure angles in degrees"),
+    op(&["exact"], Style(Notation::Exact), "( -- )", "Display fractions exactly, as in `1/3`"),
+    op(&["expand"], Style(Notation::Expansion), "( -- )", "Display fractions to `prec` digits, as in `0.333…`"),
+    op(&["eng"], Style(Notation::Engineering), "( -- )", "Display numbers in engineering notation, as in `12.5e3`"),
+    command(&["group"], parse_group, "( -- )", "Group decimal digits in threes: `group ,`, `group _` or `group off`"),
+    op(&["bin"], Radix(Base::Binary), "( -- )", "Display the stack in binary"),
+    op(&["oct"], Radix(Base::Octal), "( -- )", "Display the stack in octal"),
+    op(&["dec"], Radix(Base::Decimal), "( -- )", "Display the stack in decimal"),
+    op(&["hex"], Radix(Base::Hexadecimal), "( -- )", "Display the stack in hexadecimal"),
+    command(&["show"], parse_show, "( -- )", "Choose what is printed: `show top`, `show all` or `show changed`"),
+    command(&["order"], parse_order, "( -- )", "Print the stack `order top` or `order bottom` first"),
+    command(&["lenient"], parse_lenient, "( -- )", "Skip unreadable tokens and undefined names with a warning: `lenient on` or `lenient off`"),
+    command(&["trace"], parse_trace, "( -- )", "Print each token and the stack after it: `trace on` or `trace off`"),
+    command(&["save"], parse_save, "( -- )", "Save the stack, variables, words and modes: `save file`"),
+    command(&["load"], parse_load, "(... -- ...)", "Replace the session with a saved one: `load file`"),
+    op(&["netaddr"], Network, "(addr n -- net)", "The first address of the /n subnet holding addr"),
+    op(&["bcast"], Broadcast, "(addr n -- bcast)", "The last address of the /n subnet holding addr"),
+    op(&["hosts"], Hosts, "(n -- count)", "The number of host addresses in a /n subnet"),
+    op(&["innet"], InNetwork, "(addr net n -- flag)", "1 if addr is in the /n subnet holding net, else 0"),
+    op(&["ip"], ShowAddress, "(a -- a)", "Print the top entry as an IPv4 address"),
+    op(&["approx"], Approximate, "(a max -- b)", "The closest fraction to a with a denominator of at most max"),
+    op(&["cf"], ShowContinuedFraction, "(a -- a)", "Print the top entry as a continued fraction, as in `[3;7,16]`"),
+    op(&["bits"], Bits, "(a -- a)", "Print the bits of the word, grouped by nibble and numbered"),
+    op(&["help"], Help(None), "( -- )", "Describe every operator, or one: `help sqrt`"),
+    op(&["browse"], Browse, "( -- )", "Inspect, delete and reorder entries in a full screen view of the stack"),
+    command(&["set"], parse_set, "( -- )", "Change a setting of the REPL: `set keymap vi` or `set keymap emacs`"),
+];
+
+/// Find the operator spelled `name`.
+pub fn find(name: &str) -> Option<&'static Operator> {
+    OPERATORS.iter().find(|op| op.names.contains(&name))
+}
+
+/// The first spelling of the operator that reads as `token`.
+pub fn name(token: &Token) -> Option<&'static str> {
+    OPERATORS
+        .iter()
+        .find(|op| matches!(&op.syntax, Syntax::Token(t) if t == token))
+        .map(|op| op.names[0])
+}
+
+/// Find the operator with the longest symbolic spelling that `from` starts with, along with the
+/// length of that spelling.
+pub fn find_symbol(from: &str) -> Option<(&'static Operator, usize)> {
+    OPERATORS
+        .iter()
+        .flat_map(|op| op.names.iter().map(move |name| (op, name)))
+        .filter(|(_, name)| !name.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .filter(|(_, name)| from.starts_with(*name))
+        .map(|(op, name)| (op, name.len()))
+        .max_by_key(|&(_, len)| len)
+}
+
+/// Describe the operator named `topic`, or every operator when there is no topic.
+pub fn help(topic: Option<&str>) -> String {
+    let mut text = String::new();
+    let ops: Vec<&Operator> = match topic.and_then(find) {
+        Some(op) => vec![op],
+        None => OPERATORS.iter().collect(),
+    };
+    for op in ops {
+        // Writing to a String can't fail.
+        let _ = writeln!(
+            text,
+            "{:<10} {:<30} {}",
+            op.names.join(" "),
+            op.effect,
+            op.description
+        );
+    }
+    text.pop();
+    text
+}
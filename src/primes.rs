@@ -0,0 +1,237 @@
+//! Prime numbers.
+//!
+//! Primality is tested with Miller–Rabin against a fixed set of bases. The first 13 of them are
+//! enough to make the test exact below 3.3 × 10^24, and the rest make it very unlikely that a
+//! larger composite passes.
+//!
+//! Searching and factoring can take a long time, so they check for an interrupt as they go.
+use crate::error::CalcError;
+use crate::number::Int;
+use crate::Interrupt;
+use alloc::vec;
+use alloc::vec::Vec;
+use num_traits::{One, Zero};
+
+/// The bases that Miller–Rabin tests against, which are also used for trial division.
+const BASES: &[u32] = &[
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+/// Whether `n` is prime.
+pub(crate) fn is_prime(n: &Int) -> bool {
+    if *n < 2 {
+        return false;
+    }
+    for &base in BASES {
+        if *n == Int::from(base) {
+            return true;
+        }
+        if (n % Int::from(base)).is_zero() {
+            return false;
+        }
+    }
+    // Write n - 1 as d * 2^s with d odd.
+    let minus_one = n - Int::one();
+    let s = minus_one.trailing_zeros();
+    let d = &minus_one >> s as usize;
+    BASES.iter().all(|&base| {
+        let mut x = Int::from(base).pow_mod(&d, n);
+        if x.is_one() || x == minus_one {
+            return true;
+        }
+        for _ in 1..s {
+            x = x.square() % n;
+            if x == minus_one {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// The smallest prime greater than `n`.
+pub(crate) fn next_prime(n: &Int, interrupt: &Interrupt) -> Result<Int, CalcError> {
+    if *n < 2 {
+        return Ok(Int::from(2));
+    }
+    // Past 2, only odd numbers need testing.
+    let mut candidate = n + Int::one();
+    if candidate.is_even() && candidate != 2 {
+        candidate += 1;
+    }
+    while !is_prime(&candidate) {
+        interrupt.check()?;
+        candidate += 2;
+    }
+    Ok(candidate)
+}
+
+/// The largest prime less than `n`, if there is one.
+pub(crate) fn previous_prime(n: &Int, interrupt: &Interrupt) -> Result<Option<Int>, CalcError> {
+    if *n <= 2 {
+        return Ok(None);
+    }
+    if *n == 3 {
+        return Ok(Some(Int::from(2)));
+    }
+    let mut candidate = n - Int::one();
+    if candidate.is_even() {
+        candidate -= 1;
+    }
+    while !is_prime(&candidate) {
+        interrupt.check()?;
+        candidate -= 2;
+    }
+    Ok(Some(candidate))
+}
+
+/// Divisors up to this are found by trial division, before turning to Pollard's rho.
+const TRIAL_DIVISORS: u32 = 1000;
+
+/// The prime factors of `n`, which is at least 2, in increasing order and repeated as many
+/// times as they divide it.
+pub(crate) fn factor(mut n: Int, interrupt: &Interrupt) -> Result<Vec<Int>, CalcError> {
+    let mut factors = Vec::new();
+    for divisor in 2..TRIAL_DIVISORS {
+        let divisor = Int::from(divisor);
+        if &divisor * &divisor > n {
+            break;
+        }
+        // Every smaller prime has been divided out, so only primes divide.
+        while (&n % &divisor).is_zero() {
+            n /= &divisor;
+            factors.push(divisor.clone());
+        }
+    }
+    let mut pending = vec![n];
+    while let Some(n) = pending.pop() {
+        if n.is_one() {
+            continue;
+        }
+        if is_prime(&n) {
+            factors.push(n);
+        } else {
+            let divisor = rho(&n, interrupt)?;
+            pending.push(&n / &divisor);
+            pending.push(divisor);
+        }
+    }
+    factors.sort();
+    Ok(factors)
+}
+
+/// A nontrivial divisor of the composite `n`, found with Brent's variant of Pollard's rho.
+fn rho(n: &Int, interrupt: &Interrupt) -> Result<Int, CalcError> {
+    // How many steps are multiplied together before taking a gcd.
+    const BATCH: usize = 128;
+    let mut increment = 1;
+    loop {
+        let step = |x: &Int| (x.square() + Int::from(increment)) % n;
+        let (mut x, mut y, mut saved) = (Int::zero(), Int::from(2), Int::zero());
+        let (mut product, mut divisor, mut length) = (Int::one(), Int::one(), 1);
+        while divisor.is_one() {
+            interrupt.check()?;
+            x = y.clone();
+            for _ in 0..length {
+                y = step(&y);
+            }
+            let mut done = 0;
+            while done < length && divisor.is_one() {
+                interrupt.check()?;
+                saved = y.clone();
+                for _ in 0..BATCH.min(length - done) {
+                    y = step(&y);
+                    product = product * (&x - &y).abs() % n;
+                }
+                divisor = product.gcd(n).abs();
+                done += BATCH;
+            }
+            length *= 2;
+        }
+        if divisor == *n {
+            // The batch overshot, so retrace it one step at a time.
+            loop {
+                saved = step(&saved);
+                divisor = (&x - &saved).abs().gcd(n).abs();
+                if !divisor.is_one() {
+                    break;
+                }
+            }
+        }
+        if divisor != *n {
+            return Ok(divisor);
+        }
+        // This sequence cycled without splitting n, so try another.
+        increment += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(digits: &str) -> Int {
+        digits.parse().unwrap()
+    }
+
+    fn factors(n: &str) -> Vec<Int> {
+        factor(int(n), &Interrupt::default()).unwrap()
+    }
+
+    #[test]
+    fn primes() {
+        for prime in &["2", "3", "71", "73", "1009", "2147483647"] {
+            assert!(is_prime(&int(prime)), "{}", prime);
+        }
+        assert!(is_prime(&int("2305843009213693951")));
+        // Past the range where the bases are known to be exact.
+        assert!(is_prime(&int("618970019642690137449562111")));
+        for composite in &["0", "1", "4", "1018081", "4611686014132420609"] {
+            assert!(!is_prime(&int(composite)), "{}", composite);
+        }
+    }
+
+    #[test]
+    fn pseudoprimes() {
+        // Carmichael numbers pass Fermat's test for every base coprime to them.
+        for carmichael in &["561", "1105", "1729", "2465", "2821", "6601", "8911"] {
+            assert!(!is_prime(&int(carmichael)), "{}", carmichael);
+        }
+        // Strong pseudoprimes to every prime base up to 23, 37 and 41 respectively.
+        for pseudoprime in &[
+            "3825123056546413051",
+            "318665857834031151167461",
+            "3317044064679887385961981",
+        ] {
+            assert!(!is_prime(&int(pseudoprime)), "{}", pseudoprime);
+        }
+    }
+
+    #[test]
+    fn small_factors() {
+        assert_eq!(factors("2"), [int("2")]);
+        assert_eq!(factors("561"), [int("3"), int("11"), int("17")]);
+        assert_eq!(factors("1024"), vec![int("2"); 10]);
+        let expected = [int("71"), int("839"), int("1471"), int("6857")];
+        assert_eq!(factors("600851475143"), expected);
+    }
+
+    #[test]
+    fn large_factors() {
+        // Each of these has factors past trial division.
+        assert_eq!(factors("1018081"), [int("1009"), int("1009")]);
+        let expected = [int("2147483647"), int("2147483647")];
+        assert_eq!(factors("4611686014132420609"), expected);
+        let expected = [int("274177"), int("67280421310721")];
+        assert_eq!(factors("18446744073709551617"), expected);
+        let expected = [int("149491"), int("747451"), int("34233211")];
+        assert_eq!(factors("3825123056546413051"), expected);
+        let expected = [int("399165290221"), int("798330580441")];
+        assert_eq!(factors("318665857834031151167461"), expected);
+        let prime = int("2305843009213693951");
+        assert_eq!(
+            factor(prime.clone(), &Interrupt::default()).unwrap(),
+            [prime]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Statistics of the entries on the stack.
+//!
+//! Everything but the standard deviation is exact. The standard deviation is the square root of
+//! the variance, so it's approximated to the precision like `sqrt` when that isn't rational.
+use crate::approx;
+use crate::error::CalcError;
+use crate::number::Rational;
+use crate::Interrupt;
+use alloc::vec::Vec;
+use num_traits::Zero;
+
+/// The statistics that summarize a group of entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Statistic {
+    Mean,
+    Median,
+    /// The sample variance, dividing by one less than the number of entries.
+    Variance,
+    /// The sample standard deviation.
+    StdDev,
+}
+
+impl Statistic {
+    /// The fewest entries that the statistic is defined for.
+    pub(crate) fn min_count(self) -> usize {
+        match self {
+            Statistic::Mean | Statistic::Median => 1,
+            Statistic::Variance | Statistic::StdDev => 2,
+        }
+    }
+
+    /// Summarize `values`, which must hold at least [`min_count`](Self::min_count) of them.
+    pub(crate) fn compute(
+        self,
+        mut values: Vec<Rational>,
+        precision: usize,
+        interrupt: &Interrupt,
+    ) -> Result<Rational, CalcError> {
+        if values.len() < self.min_count() {
+            return Err(CalcError::TooFewEntries(self.min_count()));
+        }
+        Ok(match self {
+            Statistic::Mean => mean(&values),
+            Statistic::Median => {
+                values.sort();
+                let middle = values.len() / 2;
+                if values.len() % 2 == 1 {
+                    values.swap_remove(middle)
+                } else {
+                    mean(&values[middle - 1..=middle])
+                }
+            }
+            Statistic::Variance => variance(&values),
+            // The variance of real numbers is never negative.
+            Statistic::StdDev => approx::sqrt(variance(&values), precision, interrupt)?.unwrap(),
+        })
+    }
+}
+
+fn mean(values: &[Rational]) -> Rational {
+    let sum = values
+        .iter()
+        .fold(Rational::zero(), |sum, x| sum + x.clone());
+    sum / Rational::from(values.len())
+}
+
+fn variance(values: &[Rational]) -> Rational {
+    let mean = mean(values);
+    let squares = values.iter().fold(Rational::zero(), |sum, x| {
+        let deviation = x.clone() - mean.clone();
+        sum + deviation.clone() * deviation
+    });
+    squares / Rational::from(values.len() - 1)
+}
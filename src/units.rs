@@ -0,0 +1,501 @@
+//! Quantities with physical units.
+//!
+//! A number may be followed directly by a unit, as in `3m`, `2h` or `9.8m/s^2`. Units are
+//! products of the names below raised to integer powers, separated by `*` and `/`. Each `/`
+//! divides by the factor after it only, so `J/kg/K` is joules per kilogram per kelvin.
+//!
+//! Adding or subtracting quantities converts the right operand into the unit of the left one,
+//! and fails if their dimensions differ. Multiplying or dividing two quantities gives a result
+//! in SI base units, which `to` converts to any unit with the same dimensions.
+//!
+//! Durations may also be written in several parts, largest first, as in `1h30m` or `2d12h`.
+//! These are in seconds, and the `m` in them is minutes rather than metres.
+use crate::date;
+use crate::error::CalcError;
+use crate::number::{Int, Rational};
+use crate::Interrupt;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use num_traits::{One, Zero};
+
+/// The number of SI base dimensions.
+const DIMENSIONS: usize = 7;
+
+/// The exponent of each SI base dimension, in the order of [`BASE_UNITS`].
+type Dimension = [i32; DIMENSIONS];
+
+/// The names of the SI base units of length, mass, time, current, temperature, amount of
+/// substance and luminous intensity.
+const BASE_UNITS: [&str; DIMENSIONS] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: Dimension = [0, 0, 0, 0, 0, 0, 1];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0];
+const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: Dimension = [2, 1, -3, -2, 0, 0, 0];
+
+/// Every unit that can be named, with its size in SI base units as a numerator and
+/// denominator.
+const UNITS: &[(&str, u64, u64, Dimension)] = &[
+    ("m", 1, 1, LENGTH),
+    ("km", 1000, 1, LENGTH),
+    ("cm", 1, 100, LENGTH),
+    ("mm", 1, 1000, LENGTH),
+    ("um", 1, 1_000_000, LENGTH),
+    ("nm", 1, 1_000_000_000, LENGTH),
+    ("in", 254, 10_000, LENGTH),
+    ("ft", 3048, 10_000, LENGTH),
+    ("yd", 9144, 10_000, LENGTH),
+    ("mi", 1_609_344, 1000, LENGTH),
+    ("ha", 10_000, 1, AREA),
+    ("L", 1, 1000, VOLUME),
+    ("mL", 1, 1_000_000, VOLUME),
+    ("kg", 1, 1, MASS),
+    ("g", 1, 1000, MASS),
+    ("mg", 1, 1_000_000, MASS),
+    ("t", 1000, 1, MASS),
+    ("lb", 45_359_237, 100_000_000, MASS),
+    ("oz", 45_359_237, 1_600_000_000, MASS),
+    ("s", 1, 1, TIME),
+    ("ms", 1, 1000, TIME),
+    ("us", 1, 1_000_000, TIME),
+    ("ns", 1, 1_000_000_000, TIME),
+    ("min", 60, 1, TIME),
+    ("h", 3600, 1, TIME),
+    ("d", 86_400, 1, TIME),
+    ("Hz", 1, 1, FREQUENCY),
+    ("kHz", 1000, 1, FREQUENCY),
+    ("MHz", 1_000_000, 1, FREQUENCY),
+    ("GHz", 1_000_000_000, 1, FREQUENCY),
+    ("A", 1, 1, CURRENT),
+    ("mA", 1, 1000, CURRENT),
+    ("K", 1, 1, TEMPERATURE),
+    ("mol", 1, 1, AMOUNT),
+    ("cd", 1, 1, LUMINOSITY),
+    ("N", 1, 1, FORCE),
+    ("Pa", 1, 1, PRESSURE),
+    ("kPa", 1000, 1, PRESSURE),
+    ("bar", 100_000, 1, PRESSURE),
+    ("J", 1, 1, ENERGY),
+    ("kJ", 1000, 1, ENERGY),
+    ("Wh", 3600, 1, ENERGY),
+    ("kWh", 3_600_000, 1, ENERGY),
+    ("W", 1, 1, POWER),
+    ("kW", 1000, 1, POWER),
+    ("C", 1, 1, CHARGE),
+    ("V", 1, 1, VOLTAGE),
+    ("ohm", 1, 1, RESISTANCE),
+];
+
+/// The parts of a duration, largest first, and their length in seconds.
+const DURATION_PARTS: &[(&str, u64)] = &[("d", 86_400), ("h", 3600), ("m", 60), ("s", 1)];
+
+/// The largest power that a unit, or any dimension of a quantity, may be raised to.
+pub(crate) const MAX_POWER: i32 = 100;
+
+/// `num / den` raised to the integer power `exp`.
+fn powi(num: u64, den: u64, exp: i32) -> Rational {
+    let times = exp.unsigned_abs() as usize;
+    let (num, den) = (Int::from(num).pow(times), Int::from(den).pow(times));
+    if exp < 0 {
+        Rational::new(den, num)
+    } else {
+        Rational::new(num, den)
+    }
+}
+
+/// Check that the power of a dimension, if it could be computed, is within [`MAX_POWER`].
+fn checked_power(power: Option<i32>) -> Result<i32, CalcError> {
+    match power {
+        Some(power) if (-MAX_POWER..=MAX_POWER).contains(&power) => Ok(power),
+        _ => Err(CalcError::UnitPowerTooLarge),
+    }
+}
+
+/// A unit of measure, such as `km/h`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    /// How the unit is written.
+    name: String,
+    /// The size of the unit in SI base units.
+    scale: Rational,
+    dimension: Dimension,
+}
+
+impl Unit {
+    /// Parse a unit such as `m`, `kg*m/s^2` or `km/h`.
+    ///
+    /// Returns `None` for unknown names, malformed powers, units without dimensions and powers
+    /// beyond [`MAX_POWER`].
+    pub fn parse(from: &str) -> Option<Unit> {
+        let mut scale = Rational::one();
+        let mut dimension = Dimension::default();
+        let mut divide = false;
+        let mut rest = from;
+        loop {
+            let end = rest.find(['*', '/']).unwrap_or(rest.len());
+            let (factor, after) = rest.split_at(end);
+            let (name, power) = match factor.split_once('^') {
+                Some((name, power)) => (name, power.parse::<i32>().ok()?),
+                None => (factor, 1),
+            };
+            let &(_, num, den, dims) = UNITS.iter().find(|(unit, ..)| *unit == name)?;
+            checked_power(Some(power)).ok()?;
+            let power = if divide { -power } else { power };
+            scale *= powi(num, den, power);
+            for (total, dim) in dimension.iter_mut().zip(dims) {
+                *total = checked_power(total.checked_add(dim * power)).ok()?;
+            }
+            match after.chars().next() {
+                Some(op) => {
+                    divide = op == '/';
+                    rest = &after[1..];
+                }
+                None => break,
+            }
+        }
+        if dimension == Dimension::default() {
+            return None;
+        }
+        scale.normalize();
+        Some(Unit {
+            name: from.to_string(),
+            scale,
+            dimension,
+        })
+    }
+
+    /// The SI base unit with `dimension`, or `None` if it has no dimensions.
+    fn base(dimension: Dimension) -> Option<Unit> {
+        if dimension == Dimension::default() {
+            return None;
+        }
+        let factor = |(name, power): (&str, i32)| match power {
+            1 => name.to_string(),
+            _ => format!("{}^{}", name, power),
+        };
+        let powers = || BASE_UNITS.iter().copied().zip(dimension);
+        let above: Vec<String> = powers().filter(|(_, p)| *p > 0).map(factor).collect();
+        let below: Vec<String> = powers()
+            .filter(|(_, p)| *p < 0)
+            .map(|(name, p)| factor((name, -p)))
+            .collect();
+        // A unit can't start with `/`, so units with only negative powers keep their signs.
+        let name = if above.is_empty() {
+            powers()
+                .filter(|(_, p)| *p < 0)
+                .map(factor)
+                .collect::<Vec<_>>()
+                .join("*")
+        } else if below.is_empty() {
+            above.join("*")
+        } else {
+            format!("{}/{}", above.join("*"), below.join("/"))
+        };
+        Some(Unit {
+            name,
+            scale: Rational::one(),
+            dimension,
+        })
+    }
+
+    /// How the unit is written.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the unit in SI base units.
+    pub(crate) fn scale(&self) -> &Rational {
+        &self.scale
+    }
+}
+
+/// A number on the stack, along with its unit if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    /// Shared, so that copying an entry, as `dup` does, doesn't copy a large number.
+    pub(crate) value: Rc<Rational>,
+    /// `None` for plain numbers. Units always have dimensions.
+    pub(crate) unit: Option<Unit>,
+    /// Whether this is a calendar date, counted in days since 1970-01-01. Dates have no unit.
+    pub(crate) date: bool,
+}
+
+impl From<Rational> for Quantity {
+    fn from(value: Rational) -> Self {
+        Quantity {
+            value: Rc::new(value),
+            unit: None,
+            date: false,
+        }
+    }
+}
+
+impl PartialEq<Rational> for Quantity {
+    fn eq(&self, other: &Rational) -> bool {
+        self.unit.is_none() && !self.date && *self.value == *other
+    }
+}
+
+impl Quantity {
+    /// A quantity of `value` in `unit`.
+    pub fn new(value: Rational, unit: Unit) -> Self {
+        Quantity {
+            value: Rc::new(value),
+            unit: Some(unit),
+            date: false,
+        }
+    }
+
+    /// The date `days` days after 1970-01-01.
+    pub(crate) fn date(days: Rational) -> Self {
+        Quantity {
+            value: Rc::new(days),
+            unit: None,
+            date: true,
+        }
+    }
+
+    /// The date written as `YYYY-MM-DD`, or `None` if this isn't a date.
+    pub fn as_date(&self) -> Option<String> {
+        match self.date {
+            true => Some(date::format(&self.value)),
+            false => None,
+        }
+    }
+
+    /// The number of units.
+    pub fn value(&self) -> &Rational {
+        &self.value
+    }
+
+    /// Take the value, copying it only if it's shared with another entry.
+    fn into_value(self) -> Rational {
+        Rc::unwrap_or_clone(self.value)
+    }
+
+    /// The unit, or `None` for a plain number.
+    pub fn unit(&self) -> Option<&Unit> {
+        self.unit.as_ref()
+    }
+
+    fn dimension(&self) -> Dimension {
+        self.unit
+            .as_ref()
+            .map_or_else(Dimension::default, |unit| unit.dimension)
+    }
+
+    /// The value in SI base units.
+    pub(crate) fn base_value(&self) -> Rational {
+        match &self.unit {
+            Some(unit) => Rational::clone(&self.value) * unit.scale.clone(),
+            None => Rational::clone(&self.value),
+        }
+    }
+
+    /// A quantity of `value` SI base units with `dimension`.
+    fn from_base(value: Rational, dimension: Dimension) -> Self {
+        Quantity {
+            value: Rc::new(value),
+            unit: Unit::base(dimension),
+            date: false,
+        }
+    }
+
+    /// Parse a duration of two or more parts, such as `1h30m`, into seconds, with parts of at
+    /// most `max_bits` bits.
+    pub(crate) fn duration(from: &str, max_bits: Option<usize>) -> Option<Quantity> {
+        let mut parts = DURATION_PARTS.iter();
+        let (mut seconds, mut count) = (Rational::zero(), 0);
+        let mut rest = from;
+        while !rest.is_empty() {
+            let name_start = rest.find(|c: char| c.is_ascii_alphabetic())?;
+            let name_end = rest[name_start..]
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .map_or(rest.len(), |end| name_start + end);
+            // Finding the part in what's left of the list keeps them in order.
+            let name = &rest[name_start..name_end];
+            let &(_, scale) = parts.find(|(part, _)| *part == name)?;
+            let value = crate::parse_decimal(&rest[..name_start], max_bits).ok()?;
+            seconds += value * Rational::from(scale);
+            count += 1;
+            rest = &rest[name_end..];
+        }
+        if count < 2 {
+            return None;
+        }
+        Some(Quantity::new(seconds, Unit::parse("s")?))
+    }
+
+    /// Take the plain number out of the quantity, rejecting anything with a unit.
+    pub(crate) fn into_number(self) -> Result<Rational, CalcError> {
+        match self.unit {
+            Some(_) => Err(CalcError::UnexpectedUnit),
+            None if self.date => Err(CalcError::DateArithmetic),
+            None => Ok(self.into_value()),
+        }
+    }
+
+    /// The number of days in a plain number or a duration.
+    fn days(&self) -> Result<Rational, CalcError> {
+        match self.dimension() {
+            TIME => Ok(self.base_value() / Rational::from(86_400)),
+            dimension if dimension == Dimension::default() => self.clone().into_number(),
+            _ => Err(CalcError::IncompatibleUnits),
+        }
+    }
+
+    /// Add two quantities, or shift a date later by a number of days or a duration.
+    pub(crate) fn add(self, rhs: Quantity) -> Result<Quantity, CalcError> {
+        match (self.date, rhs.date) {
+            (false, false) => self.combine(rhs, |lhs, rhs| lhs + rhs),
+            (true, false) => Ok(Quantity::date(date::shift(self.into_value(), rhs.days()?)?)),
+            (false, true) => rhs.add(self),
+            (true, true) => Err(CalcError::DateArithmetic),
+        }
+    }
+
+    /// Subtract two quantities, shift a date earlier, or count the days between two dates.
+    pub(crate) fn subtract(self, rhs: Quantity) -> Result<Quantity, CalcError> {
+        match (self.date, rhs.date) {
+            (false, false) => self.combine(rhs, |lhs, rhs| lhs - rhs),
+            (true, false) => Ok(Quantity::date(date::shift(
+                self.into_value(),
+                -rhs.days()?,
+            )?)),
+            (true, true) => Ok((self.into_value() - rhs.into_value()).into()),
+            (false, true) => Err(CalcError::DateArithmetic),
+        }
+    }
+
+    /// Apply `f` to the value, keeping the unit. Dates are rejected.
+    pub(crate) fn map(self, f: impl FnOnce(Rational) -> Rational) -> Result<Self, CalcError> {
+        if self.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        Ok(Quantity {
+            value: Rc::new(f(Rc::unwrap_or_clone(self.value))),
+            ..self
+        })
+    }
+
+    /// Convert to `unit`, which must have the same dimensions.
+    pub(crate) fn convert(&self, unit: &Unit) -> Result<Quantity, CalcError> {
+        if self.dimension() != unit.dimension {
+            return Err(CalcError::IncompatibleUnits);
+        }
+        Ok(Quantity::new(
+            self.base_value() / unit.scale.clone(),
+            unit.clone(),
+        ))
+    }
+
+    /// Apply `f` to the values of two quantities with the same dimensions, giving a result in
+    /// the unit of `self`.
+    pub(crate) fn combine(
+        self,
+        rhs: Quantity,
+        f: impl FnOnce(Rational, Rational) -> Rational,
+    ) -> Result<Quantity, CalcError> {
+        if self.date || rhs.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        if self.dimension() != rhs.dimension() {
+            return Err(CalcError::IncompatibleUnits);
+        }
+        let rhs = match &self.unit {
+            Some(unit) => rhs.base_value() / unit.scale.clone(),
+            None => rhs.into_value(),
+        };
+        self.map(|lhs| f(lhs, rhs))
+    }
+
+    /// Order two quantities with the same dimensions, or two dates.
+    pub(crate) fn compare(&self, rhs: &Quantity) -> Result<Ordering, CalcError> {
+        if self.date != rhs.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        if self.dimension() != rhs.dimension() {
+            return Err(CalcError::IncompatibleUnits);
+        }
+        Ok(self.base_value().cmp(&rhs.base_value()))
+    }
+
+    /// Multiply two quantities.
+    pub(crate) fn multiply(self, rhs: Quantity) -> Result<Quantity, CalcError> {
+        if self.date || rhs.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        Ok(match (&self.unit, &rhs.unit) {
+            (_, None) => self.map(|lhs| lhs * rhs.into_value())?,
+            (None, _) => rhs.map(|rhs| self.into_value() * rhs)?,
+            (Some(lhs_unit), Some(rhs_unit)) => {
+                let mut dimension = lhs_unit.dimension;
+                for (total, dim) in dimension.iter_mut().zip(rhs_unit.dimension) {
+                    *total = checked_power(total.checked_add(dim))?;
+                }
+                Quantity::from_base(self.base_value() * rhs.base_value(), dimension)
+            }
+        })
+    }
+
+    /// Divide two quantities. The divisor must not be zero.
+    pub(crate) fn divide(self, rhs: Quantity) -> Result<Quantity, CalcError> {
+        if self.date || rhs.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        Ok(match rhs.unit {
+            None => self.map(|lhs| lhs / rhs.into_value())?,
+            Some(ref rhs_unit) => {
+                let mut dimension = self.dimension();
+                for (total, dim) in dimension.iter_mut().zip(rhs_unit.dimension) {
+                    *total = checked_power(total.checked_sub(dim))?;
+                }
+                Quantity::from_base(self.base_value() / rhs.base_value(), dimension)
+            }
+        })
+    }
+
+    /// Raise the quantity to the integer power `exp`.
+    pub(crate) fn pow(self, exp: Rational, interrupt: &Interrupt) -> Result<Quantity, CalcError> {
+        if self.date {
+            return Err(CalcError::DateArithmetic);
+        }
+        let unit = match &self.unit {
+            Some(unit) => unit,
+            None => return Ok(crate::pow(self.into_value(), exp, interrupt)?.into()),
+        };
+        let (power, den) = exp.clone().into_parts();
+        if !den.is_one() {
+            return Err(CalcError::NonIntegerExponent);
+        }
+        if power.clone().abs() > i32::MAX {
+            return Err(CalcError::ExponentTooLarge);
+        }
+        let power = i32::from(&power);
+        let mut dimension = unit.dimension;
+        for dim in dimension.iter_mut() {
+            *dim = checked_power(dim.checked_mul(power))?;
+        }
+        let value = crate::pow(self.base_value(), exp, interrupt)?;
+        if power == 0 {
+            return Ok(value.into());
+        }
+        Ok(Quantity::from_base(value, dimension))
+    }
+}
@@ -0,0 +1,81 @@
+//! The interface for JavaScript, when built for `wasm32-unknown-unknown` without the `cli`
+//! feature.
+//!
+//! Each instance has one calculator. To evaluate a line, JavaScript copies its UTF-8 into a
+//! buffer from [`alloc`], passes the buffer to [`eval_line`], and reads [`result_len`] bytes of
+//! JSON from the pointer that it returns, such as
+//! `{"stack": [{"num": "5", "den": "1"}], "output": [], "errors": []}`. The stack is written
+//! as for `--output json`, and each error as
+//! `{"message": "Division by zero", "start": 4, "end": 5}`, with the byte offsets of the span
+//! of the line that caused it.
+//!
+//! The random numbers start from a fixed seed, since there's no system to ask for one. To get
+//! different numbers each time, evaluate a line like `seed 1234` with a seed from JavaScript.
+use crate::{json, Calculator};
+use std::cell::RefCell;
+
+thread_local! {
+    static CALCULATOR: RefCell<Calculator> = RefCell::new(Calculator::default());
+    /// The result of the last line, kept until the next one so that JavaScript can read it.
+    static RESULT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Allocate `len` bytes for JavaScript to write a line into. [`eval_line`] frees them.
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::with_capacity(len);
+    let pointer = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    pointer
+}
+
+/// Evaluate the line in the `len` bytes at `line`, returning a pointer to the JSON result.
+///
+/// # Safety
+///
+/// `line` must come from `alloc(len)`, with all `len` bytes written, and isn't valid after.
+#[no_mangle]
+pub unsafe extern "C" fn eval_line(line: *mut u8, len: usize) -> *const u8 {
+    let line = Vec::from_raw_parts(line, len, len);
+    let line = String::from_utf8_lossy(&line);
+    let result = CALCULATOR.with(|calculator| evaluate(&mut calculator.borrow_mut(), &line));
+    RESULT.with(|last| {
+        *last.borrow_mut() = result;
+        last.borrow().as_ptr()
+    })
+}
+
+/// The length in bytes of the result of the last [`eval_line`].
+#[no_mangle]
+pub extern "C" fn result_len() -> usize {
+    RESULT.with(|last| last.borrow().len())
+}
+
+/// Evaluate `line`, and write the stack, any text from commands and any errors as JSON.
+fn evaluate(calculator: &mut Calculator, line: &str) -> String {
+    let errors: Vec<String> = match calculator.parse(line) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .iter()
+            .map(|error| {
+                format!(
+                    r#"{{"message": {}, "start": {}, "end": {}}}"#,
+                    json::string(&error.to_string()),
+                    error.span.start,
+                    error.span.end,
+                )
+            })
+            .collect(),
+    };
+    let output: Vec<String> = calculator
+        .take_output()
+        .iter()
+        .map(|text| json::string(text))
+        .collect();
+    format!(
+        r#"{{"stack": {}, "output": [{}], "errors": [{}]}}"#,
+        json::stack(calculator.stack()),
+        output.join(", "),
+        errors.join(", "),
+    )
+}